@@ -36,4 +36,14 @@ impl CtxClock {
 
         newage
     }
+
+    // Advance to a time supplied from outside, ignoring fixtick and the
+    // real clock.
+    pub fn tickat(&mut self, newage: f64) -> f64 {
+        self.ticklen = (newage - self.age) as f32;
+        self.age = newage;
+        self.tickcount += 1;
+
+        newage
+    }
 }
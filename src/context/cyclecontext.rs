@@ -2,22 +2,24 @@ use std::mem;
 use std::cell::RefCell;
 
 use crate::pixel::Pix;
-use crate::runner::{Runner, RunContext, RunContextWrap, PixBuffer};
+use crate::error::BeaconError;
+use crate::runner::{Runner, RunContext, RunContextWrap, PixBuffer, derive_seed};
 use crate::clock::CtxClock;
 
 #[derive(Clone)]
 pub struct CycleRunner {
-    pub runners: Box<Vec<Runner>>,
+    pub runners: Vec<Runner>,
     pub interval: f32,
     label: String,
 }
 
 impl CycleRunner {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(runners: Vec<Runner>, interval: f32) -> Runner {
         let count = runners.len();
         assert!(count > 0);
         let run = CycleRunner {
-            runners: Box::new(runners),
+            runners: runners,
             interval: interval,
             label: format!("{} scripts", count),
         };
@@ -27,34 +29,41 @@ impl CycleRunner {
     pub fn getname(&self) -> &str {
         return &self.label;
     }
+
+    pub fn getduration(&self) -> Option<f32> {
+        None
+    }
     
-    pub fn build(&self, size: usize, fixtick: Option<u32>) -> Result<RunContextWrap, String> {
-        let ctx = CycleContext::new(self.runners.clone(), self.interval, size, fixtick)?;
+    pub fn build(&self, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> Result<RunContextWrap, BeaconError> {
+        let ctx = CycleContext::new(self.runners.clone(), self.interval, size, fixtick, seed)?;
         Ok(RunContextWrap::Cycle(ctx))
     }
 }
 
 pub struct CycleContext {
-    runners: Box<Vec<Runner>>,
+    runners: Vec<Runner>,
     interval: f32,
     fadetime: f32,
     size: usize,
     fixtick: Option<u32>,
+    seed: Option<u64>,
     clock: CtxClock,
 
     curindex: usize,
+    changecount: u64, // children built so far
     curchild: Box<RunContextWrap>,
     lastchild: Option<Box<RunContextWrap>>,
-    lastchange: f32,
+    lastchange: f32, // when curchild started
+    lastbirth: f32,  // when lastchild started
     nextchange: f32,
 
     changebuf: RefCell<Vec<Pix<f32>>>,
 }
 
 impl CycleContext {
-    pub fn new(runners: Box<Vec<Runner>>, interval: f32, size: usize, fixtick: Option<u32>) -> Result<CycleContext, String> {
+    pub fn new(runners: Vec<Runner>, interval: f32, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> Result<CycleContext, BeaconError> {
         let runner = runners[0].clone();
-        let child = runner.build(size, fixtick)?;
+        let child = runner.build(size, fixtick, seed.map(|val| derive_seed(val, 0)))?;
         
         let ctx = CycleContext {
             runners: runners,
@@ -62,31 +71,37 @@ impl CycleContext {
             fadetime: 0.5,
             size: size,
             fixtick: fixtick,
+            seed: seed,
             clock: CtxClock::new(fixtick),
 
             curindex: 0,
+            changecount: 1,
             curchild: Box::new(child),
             lastchild: None,
             lastchange: 0.0,
-            nextchange: interval,
+            lastbirth: 0.0,
+            nextchange: runner.getduration().unwrap_or(interval),
 
             changebuf: RefCell::new(vec![Pix::new(0.0, 0.0, 0.0); size]),
         };
         Ok(ctx)
     }
-}
-
-impl RunContext for CycleContext {
-
-    fn tick(&mut self) -> Result<(), String> {
-        let newage = self.clock.tick() as f32;
 
+    // Move on to the next script if it's time, and drop the old one once
+    // it has faded out.
+    fn advance(&mut self, newage: f32) -> Result<(), BeaconError> {
         if newage > self.nextchange || self.curchild.done() {
-            self.nextchange = newage + self.interval;
             self.curindex = (self.curindex+1) % self.runners.len();
             let runner = self.runners[self.curindex].clone();
-            let newchild = runner.build(self.size, self.fixtick)?;
+            // A script may ask to be shown for longer (or shorter).
+            self.nextchange = newage + runner.getduration().unwrap_or(self.interval);
+            // Each showing of a script gets its own seed, so a replay
+            // matches all the way through.
+            let seed = self.seed.map(|val| derive_seed(val, self.changecount));
+            self.changecount += 1;
+            let newchild = runner.build(self.size, self.fixtick, seed)?;
             let lastchild = mem::replace(&mut self.curchild, Box::new(newchild));
+            self.lastbirth = self.lastchange;
             self.lastchange = newage;
             self.lastchild = Some(lastchild);
         }
@@ -94,7 +109,16 @@ impl RunContext for CycleContext {
         if self.lastchild.is_some() && newage > self.lastchange + self.fadetime {
             self.lastchild = None;
         }
-        
+        Ok(())
+    }
+}
+
+impl RunContext for CycleContext {
+
+    fn tick(&mut self) -> Result<(), BeaconError> {
+        let newage = self.clock.tick() as f32;
+        self.advance(newage)?;
+
         self.curchild.tick()?;
         if let Some(child) = &mut self.lastchild {
             child.tick()?;
@@ -102,6 +126,18 @@ impl RunContext for CycleContext {
         Ok(())
     }
 
+    fn tickat(&mut self, age: f64) -> Result<(), BeaconError> {
+        let newage = self.clock.tickat(age) as f32;
+        self.advance(newage)?;
+
+        // The children's clocks start when they do.
+        self.curchild.tickat((newage - self.lastchange) as f64)?;
+        if let Some(child) = &mut self.lastchild {
+            child.tickat((newage - self.lastbirth) as f64)?;
+        }
+        Ok(())
+    }
+
     fn age(&self) -> f64 {
         self.clock.age
     }
@@ -1,3 +1,4 @@
+use crate::error::BeaconError;
 use crate::runner::{Runner, RunContext, RunContextWrap, PixBuffer};
 
 #[derive(Clone)]
@@ -7,6 +8,7 @@ pub struct LimitRunner {
 }
 
 impl LimitRunner {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(runner: Runner, limit: f32) -> Runner {
         let run = LimitRunner {
             runner: Box::new(runner),
@@ -18,9 +20,13 @@ impl LimitRunner {
     pub fn getname(&self) -> &str {
         self.runner.getname()
     }
+
+    pub fn getduration(&self) -> Option<f32> {
+        Some(self.limit)
+    }
     
-    pub fn build(&self, size: usize, fixtick: Option<u32>) -> Result<RunContextWrap, String> {
-        let child = self.runner.build(size, fixtick)?;
+    pub fn build(&self, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> Result<RunContextWrap, BeaconError> {
+        let child = self.runner.build(size, fixtick, seed)?;
         let ctx = LimitContext::new(child, self.limit, size, fixtick);
         Ok(RunContextWrap::Limit(ctx))
     }
@@ -33,20 +39,23 @@ pub struct LimitContext {
 
 impl LimitContext {
     pub fn new(child: RunContextWrap, limit: f32, _size: usize, _fixtick: Option<u32>) -> LimitContext {
-        let ctx = LimitContext {
+        LimitContext {
             child: Box::new(child),
             limit: limit,
-        };
-        ctx
+        }
     }
 }
 
 impl RunContext for LimitContext {
 
-    fn tick(&mut self) -> Result<(), String> {
+    fn tick(&mut self) -> Result<(), BeaconError> {
         self.child.tick()
     }
 
+    fn tickat(&mut self, age: f64) -> Result<(), BeaconError> {
+        self.child.tickat(age)
+    }
+
     fn age(&self) -> f64 {
         self.child.age()
     }
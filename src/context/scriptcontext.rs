@@ -1,15 +1,17 @@
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
 use crate::pixel::Pix;
+use crate::error::BeaconError;
 use crate::clock::CtxClock;
 use crate::runner::{Runner, RunContext, RunContextWrap, PixBuffer};
 use crate::script::{Script, ScriptIndex};
 use crate::op::{Op1Ctx, Op3Ctx};
-use crate::op::{Op1Def, Op3Def};
 use crate::op::{Op1State, Op3State};
+use crate::param::ParamState;
 
 #[derive(Clone)]
 pub struct ScriptRunner {
@@ -18,6 +20,8 @@ pub struct ScriptRunner {
 }
 
 impl ScriptRunner {
+    // Runners are only ever handled as a Runner, so new() wraps itself.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(script: Script, filename: &str) -> Runner {
         let run = ScriptRunner {
             script: script,
@@ -27,12 +31,21 @@ impl ScriptRunner {
     }
 
     pub fn getname(&self) -> &str {
-        &self.filename
+        match &self.script.meta.title {
+            Some(title) => title,
+            None => &self.filename,
+        }
+    }
+
+    pub fn getduration(&self) -> Option<f32> {
+        self.script.meta.duration
     }
     
-    pub fn build(&self, size: usize, fixtick: Option<u32>) -> Result<RunContextWrap, String> {
-        let ctx = ScriptContext::new(self.script.clone(), size, fixtick);
-        Ok(RunContextWrap::Script(ctx))
+    pub fn build(&self, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> Result<RunContextWrap, BeaconError> {
+        // A seed from outside overrides the script's own.
+        let seed = seed.or(self.script.meta.seed);
+        let ctx = ScriptContext::new(self.script.clone(), size, fixtick, seed);
+        Ok(RunContextWrap::Script(Box::new(ctx)))
     }
 }
 
@@ -42,22 +55,31 @@ pub struct ScriptContext {
     clock: CtxClock,
     
     pub rng: Rc<RefCell<SmallRng>>,
+    pub paramstates: RefCell<HashMap<usize, ParamState>>, // for stateful params
 
     pub op1s: Vec<Op1Ctx>,
     pub op3s: Vec<Op3Ctx>,
+
+    flatbuf: RefCell<Vec<Pix<f32>>>, // the root, flattened against the background
 }
 
 impl ScriptContext {
-    pub fn new(script: Script, size: usize, fixtick: Option<u32>) -> ScriptContext {
+    pub fn new(script: Script, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> ScriptContext {
+        let rng = match seed {
+            Some(val) => SmallRng::seed_from_u64(val),
+            None => SmallRng::from_entropy(),
+        };
         // Gotta create this with some temporary values and then fill them in.
         let mut ctx = ScriptContext {
             script: Script::new(),
             size: size,
             clock: CtxClock::new(fixtick),
             
-            rng: Rc::new(RefCell::new(SmallRng::from_entropy())),
+            rng: Rc::new(RefCell::new(rng)),
+            paramstates: RefCell::new(HashMap::new()),
             op1s: Vec::default(),
             op3s: Vec::default(),
+            flatbuf: RefCell::new(Vec::default()),
         };
 
         let mut op1s: Vec<Op1Ctx> = Vec::default();
@@ -67,6 +89,7 @@ impl ScriptContext {
             op1s.push(Op1Ctx {
                 state: RefCell::new(Op1State::new_for(&op.op, &mut ctx)),
                 buf: RefCell::new(vec![0.0; size]),
+                lastwarn: Cell::new(None),
             });
         }
         
@@ -74,6 +97,7 @@ impl ScriptContext {
             op3s.push(Op3Ctx {
                 state: RefCell::new(Op3State::new_for(&op.op, &mut ctx)),
                 buf: RefCell::new(vec![Pix::new(0.0, 0.0, 0.0); size]),
+                lastwarn: Cell::new(None),
             });
         }
 
@@ -92,6 +116,24 @@ impl ScriptContext {
         self.clock.ticklen
     }
     
+    // Run every op, in order, at the current clock time. Bad values are
+    // cleaned up after each op, before anything else reads them.
+    fn runops(&mut self) -> Result<(), BeaconError> {
+        for ix in (0..self.script.order.len()).rev() {
+            match self.script.order[ix] {
+                ScriptIndex::Op1(val) => {
+                    Op1Ctx::tickop(self, val)?;
+                    Op1Ctx::sanitize(self, val);
+                },
+                ScriptIndex::Op3(val) => {
+                    Op3Ctx::tickop(self, val)?;
+                    Op3Ctx::sanitize(self, val);
+                },
+            }
+        }
+        Ok(())
+    }
+    
     pub fn applybuf1<F>(&self, val: usize, mut func: F)
     where F: FnMut(&[f32]) {
         let buf = self.op1s[val].buf.borrow();
@@ -107,21 +149,14 @@ impl ScriptContext {
 
 impl RunContext for ScriptContext {
 
-    fn tick(&mut self) -> Result<(), String> {
+    fn tick(&mut self) -> Result<(), BeaconError> {
         let _newage: f64 = self.clock.tick();
+        self.runops()
+    }
 
-        for ix in (0..self.script.order.len()).rev() {
-            match self.script.order[ix] {
-                ScriptIndex::Op1(val) => {
-                    Op1Ctx::tickop(self, val);
-                },
-                ScriptIndex::Op3(val) => {
-                    Op3Ctx::tickop(self, val);
-                },
-            }
-        }
-
-        Ok(())
+    fn tickat(&mut self, age: f64) -> Result<(), BeaconError> {
+        let _newage: f64 = self.clock.tickat(age);
+        self.runops()
     }
     
     fn age(&self) -> f64 {
@@ -137,7 +172,23 @@ impl RunContext for ScriptContext {
             },
             ScriptIndex::Op3(val) => {
                 let buf = self.op3s[*val].buf.borrow();
-                func(PixBuffer::Buf3(&buf));
+                match &self.script.meta.background {
+                    Some(background) => {
+                        // Reuse the buffer from the last frame; it only
+                        // needs resizing if the root's size changed.
+                        let mut flatbuf = self.flatbuf.borrow_mut();
+                        if flatbuf.len() != buf.len() {
+                            flatbuf.resize(buf.len(), Pix::new(0.0, 0.0, 0.0));
+                        }
+                        for (flat, pix) in flatbuf.iter_mut().zip(buf.iter()) {
+                            *flat = pix.flatten(background);
+                        }
+                        func(PixBuffer::Buf3(&flatbuf));
+                    },
+                    // Premultiplied colors are already flattened against
+                    // black.
+                    None => func(PixBuffer::Buf3(&buf)),
+                }
             },
         }
     }
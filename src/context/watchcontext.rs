@@ -1,31 +1,44 @@
+use std::mem;
 use std::time::SystemTime;
 
 use crate::parse;
+use crate::error::BeaconError;
 use crate::script::Script;
+use crate::parse::units::StripUnits;
 use crate::runner::{Runner, RunContext, RunContextWrap, PixBuffer};
-use crate::context::scriptcontext::{ScriptRunner, ScriptContext};
+use crate::context::scriptcontext::ScriptRunner;
 
 #[derive(Clone)]
 pub struct WatchScriptRunner {
     pub filename: String,
     pub script: Script,
+    pub ppm: f32,
 }
 
 impl WatchScriptRunner {
-    pub fn new(filename: &str, script: Script) -> Runner {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(filename: &str, script: Script, ppm: f32) -> Runner {
         let run = WatchScriptRunner {
             filename: filename.to_string(),
             script: script,
+            ppm: ppm,
         };
         Runner::WatchScript(run)
     }
     
     pub fn getname(&self) -> &str {
-        &self.filename
+        match &self.script.meta.title {
+            Some(title) => title,
+            None => &self.filename,
+        }
+    }
+
+    pub fn getduration(&self) -> Option<f32> {
+        self.script.meta.duration
     }
 
-    pub fn build(&self, size: usize, fixtick: Option<u32>) -> Result<RunContextWrap, String> {
-        let ctx = WatchScriptContext::new(&self.filename, self.script.clone(), size, fixtick)?;
+    pub fn build(&self, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> Result<RunContextWrap, BeaconError> {
+        let ctx = WatchScriptContext::new(&self.filename, self.script.clone(), size, self.ppm, fixtick, seed)?;
         Ok(RunContextWrap::WatchScript(ctx))
     }
 }
@@ -33,55 +46,120 @@ impl WatchScriptRunner {
 pub struct WatchScriptContext {
     pub filename: String,
     size: usize,
+    ppm: f32,
     fixtick: Option<u32>,
+    seed: Option<u64>,
 
-    watchtime: SystemTime,
+    sources: Vec<String>,
+    watchtimes: Vec<Option<SystemTime>>,
     child: Box<RunContextWrap>,
+    prevchild: Option<Box<RunContextWrap>>, // what ran before the last reload
+    now: f64,   // the last time given to tickat
+    birth: f64, // when the child was built, in the same terms
+    prevbirth: f64,
 }
 
 impl WatchScriptContext {
-    pub fn new(filename: &str, script: Script, size: usize, fixtick: Option<u32>) -> Result<WatchScriptContext, String> {
-        let runner = ScriptRunner::new(script, &filename);
-        let child = runner.build(size, fixtick)?;
-        let stat = std::fs::metadata(filename)
-            .map_err(|err| err.to_string())?;
-        let watchtime = stat.modified()
-            .map_err(|err| err.to_string())?;
+    pub fn new(filename: &str, script: Script, size: usize, ppm: f32, fixtick: Option<u32>, seed: Option<u64>) -> Result<WatchScriptContext, BeaconError> {
+        let mut sources = script.sources.clone();
+        if sources.is_empty() {
+            sources.push(filename.to_string());
+        }
+        let watchtimes = get_watchtimes(&sources);
+        let runner = ScriptRunner::new(script, filename);
+        let child = runner.build(size, fixtick, seed)?;
 
         let ctx = WatchScriptContext {
             filename: filename.to_string(),
             size: size,
+            ppm: ppm,
             fixtick: fixtick,
-            watchtime: watchtime,
+            seed: seed,
+            sources: sources,
+            watchtimes: watchtimes,
             child: Box::new(child),
+            prevchild: None,
+            now: 0.0,
+            birth: 0.0,
+            prevbirth: 0.0,
         };
         Ok(ctx)
     }
-}
 
-impl RunContext for WatchScriptContext {
-
-    fn tick(&mut self) -> Result<(), String> {
-        let stat = std::fs::metadata(&self.filename)
-            .map_err(|err| err.to_string())?;
-        let newtime = stat.modified()
-            .map_err(|err| err.to_string())?;
-        if newtime != self.watchtime {
+    // If any source file has changed, rebuild the child. A script that
+    // fails to parse or build leaves the old one running.
+    fn checkreload(&mut self) {
+        let newtimes = get_watchtimes(&self.sources);
+        if newtimes != self.watchtimes {
             println!("Reloading...");
-            self.watchtime = newtime;
-            match parse::parse_script(&self.filename) {
-                Ok(newscript) => {
+            self.watchtimes = newtimes;
+            // Units are resolved against the size we're actually running at.
+            let units = StripUnits::new(self.size, self.ppm);
+            let res = parse::parse_script(&self.filename, &units)
+                .map_err(BeaconError::from)
+                .and_then(|newscript| {
+                    // The set of included files may have changed.
+                    if newscript.sources != self.sources {
+                        self.sources = newscript.sources.clone();
+                        self.watchtimes = get_watchtimes(&self.sources);
+                    }
                     let newrunner = ScriptRunner::new(newscript, &self.filename);
-                    let ctx = newrunner.build(self.size, self.fixtick)?;
-                    self.child = Box::new(ctx);
+                    // Reloading starts over with the same seed.
+                    newrunner.build(self.size, self.fixtick, self.seed)
+                });
+            match res {
+                Ok(ctx) => {
+                    let prevchild = mem::replace(&mut self.child, Box::new(ctx));
+                    self.prevchild = Some(prevchild);
+                    self.prevbirth = self.birth;
+                    self.birth = self.now;
                 },
-                Err(msg) => {
-                    println!("{msg}");
+                Err(err) => {
+                    println!("{err}");
                 },
             }
         }
-        
-        self.child.tick()
+    }
+
+    // If a reloaded script fails while running, report it and go back to
+    // the script from before the reload.
+    fn recover(&mut self, res: Result<(), BeaconError>) -> Result<(), BeaconError> {
+        if let Err(err) = &res {
+            if let Some(prevchild) = self.prevchild.take() {
+                println!("{err}");
+                println!("Reverting to the previous script.");
+                self.child = prevchild;
+                self.birth = self.prevbirth;
+                return Ok(());
+            }
+        }
+        res
+    }
+}
+
+// Modification times of the script and everything it includes. A file
+// which can't be read counts as None.
+fn get_watchtimes(sources: &[String]) -> Vec<Option<SystemTime>> {
+    sources.iter().map(|filename| {
+        std::fs::metadata(filename)
+            .and_then(|stat| stat.modified())
+            .ok()
+    }).collect()
+}
+
+impl RunContext for WatchScriptContext {
+
+    fn tick(&mut self) -> Result<(), BeaconError> {
+        self.checkreload();
+        let res = self.child.tick();
+        self.recover(res)
+    }
+
+    fn tickat(&mut self, age: f64) -> Result<(), BeaconError> {
+        self.now = age;
+        self.checkreload();
+        let res = self.child.tickat(age - self.birth);
+        self.recover(res)
     }
 
     fn age(&self) -> f64 {
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use crate::parse::diag::{SourceLoc, Diagnostic, DiagKind, ParseError};
+
+// Anything that can go wrong between reading a script and showing it.
+// Every variant carries diagnostics, which point into the script where
+// that makes sense.
+#[derive(Clone, Debug)]
+pub enum BeaconError {
+    Parse(ParseError),   // the script couldn't be read or built
+    Type(ParseError),    // ops were given the wrong type of input
+    Runtime(Diagnostic), // an op failed while ticking (located at the op)
+    Output(Diagnostic),  // frames couldn't be written out or shown
+}
+
+impl BeaconError {
+    pub fn runtime(loc: &Option<SourceLoc>, message: String) -> BeaconError {
+        match loc {
+            Some(loc) => BeaconError::Runtime(Diagnostic::new(loc, message)),
+            None => BeaconError::Runtime(Diagnostic::bare(message)),
+        }
+    }
+
+    pub fn output(message: String) -> BeaconError {
+        BeaconError::Output(Diagnostic::bare(message))
+    }
+
+    pub fn diags(&self) -> Vec<&Diagnostic> {
+        match self {
+            BeaconError::Parse(err) => err.diags.iter().collect(),
+            BeaconError::Type(err) => err.diags.iter().collect(),
+            BeaconError::Runtime(diag) => vec![diag],
+            BeaconError::Output(diag) => vec![diag],
+        }
+    }
+}
+
+// A script with nothing wrong but its types is a type error; anything
+// else wrong with it makes it a parse error.
+impl From<ParseError> for BeaconError {
+    fn from(err: ParseError) -> BeaconError {
+        if !err.diags.is_empty() && err.diags.iter().all(|diag| diag.kind == DiagKind::Type) {
+            return BeaconError::Type(err);
+        }
+        BeaconError::Parse(err)
+    }
+}
+
+impl From<std::io::Error> for BeaconError {
+    fn from(err: std::io::Error) -> BeaconError {
+        BeaconError::output(err.to_string())
+    }
+}
+
+impl fmt::Display for BeaconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BeaconError::Parse(err) => write!(f, "{}", err),
+            BeaconError::Type(err) => write!(f, "{}", err),
+            BeaconError::Runtime(diag) => write!(f, "{}", diag),
+            BeaconError::Output(diag) => write!(f, "{}", diag),
+        }
+    }
+}
+
+impl std::error::Error for BeaconError {}
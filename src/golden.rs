@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::Path;
+
+use beacon::parse;
+use beacon::error::BeaconError;
+use beacon::parse::units::StripUnits;
+use beacon::runner::{RunContext, PixBuffer};
+use beacon::context::scriptcontext::ScriptRunner;
+
+// Golden-frame testing. Each script is run with a fixed seed and a fixed
+// tick rate at a few strip sizes, and some of its frames are recorded as
+// 8-bit RGB. A later run must match those within a small tolerance, so
+// that float noise doesn't count as a change.
+//
+//    beacon --golden tests/golden scripts/*.pab          (check)
+//    beacon --golden tests/golden --bless scripts/*.pab  (rewrite)
+
+const SEED: u64 = 1;
+const FPS: u32 = 60;
+const SIZES: [usize; 3] = [16, 60, 160];
+const FRAMES: [usize; 4] = [1, 30, 90, 180]; // tick numbers
+const TOLERANCE: i32 = 3; // per channel, out of 255
+
+// Returns whether every script matched. (Blessing always matches.)
+pub fn run_golden(filenames: &Vec<String>, refdir: &str, bless: bool) -> Result<bool, BeaconError> {
+    let mut allok = true;
+    for filename in filenames {
+        let name = Path::new(filename).file_stem()
+            .ok_or_else(|| BeaconError::output(format!("not a script filename: {}", filename)))?
+            .to_string_lossy().to_string();
+        let refpath = Path::new(refdir).join(format!("{}.txt", name));
+        let text = record_script(filename, &name)?;
+
+        if bless {
+            let old = fs::read_to_string(&refpath).ok();
+            if old.as_deref() != Some(text.as_str()) {
+                fs::create_dir_all(refdir)
+                    .map_err(|err| BeaconError::output(format!("{}: {}", refdir, err)))?;
+                fs::write(&refpath, &text)
+                    .map_err(|err| BeaconError::output(format!("{}: {}", refpath.display(), err)))?;
+                println!("{}: blessed", filename);
+            }
+            continue;
+        }
+
+        let reftext = match fs::read_to_string(&refpath) {
+            Ok(val) => val,
+            Err(_) => {
+                println!("{}: no reference at {} (run with --bless)", filename, refpath.display());
+                allok = false;
+                continue;
+            },
+        };
+        match compare(&reftext, &text) {
+            Ok(()) => println!("{}: ok", filename),
+            Err(msg) => {
+                println!("{}: {}", filename, msg);
+                allok = false;
+            },
+        }
+    }
+    Ok(allok)
+}
+
+// Run one script at every size and write out the recorded frames.
+fn record_script(filename: &str, name: &str) -> Result<String, BeaconError> {
+    let mut res = format!("# {} (seed {}, {} fps)\n", name, SEED, FPS);
+    for size in SIZES {
+        // Units are resolved against the size, so parse each time.
+        let units = StripUnits::new(size, 60.0);
+        let script = parse::parse_script(filename, &units)?;
+        let runner = ScriptRunner::new(script, filename);
+        let mut ctx = runner.build(size, Some(FPS), Some(SEED))?;
+
+        res.push_str(&format!("size {}\n", size));
+        let mut tickcount = 0;
+        for frame in FRAMES {
+            while tickcount < frame {
+                ctx.tick()?;
+                tickcount += 1;
+            }
+            let mut line = format!("frame {} ", frame);
+            ctx.applybuf(|pixbuf| {
+                match pixbuf {
+                    PixBuffer::Buf1(buf) => {
+                        for val in buf {
+                            let byte = quantize(*val);
+                            line.push_str(&format!("{:02X}{:02X}{:02X}", byte, byte, byte));
+                        }
+                    },
+                    PixBuffer::Buf3(buf) => {
+                        for pix in buf {
+                            line.push_str(&format!("{:02X}{:02X}{:02X}", quantize(pix.r), quantize(pix.g), quantize(pix.b)));
+                        }
+                    },
+                }
+            });
+            res.push_str(&line);
+            res.push('\n');
+        }
+    }
+    Ok(res)
+}
+
+fn quantize(val: f32) -> u8 {
+    (val.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Compare two recordings line by line. Frame lines may differ by the
+// tolerance in each channel; everything else must match exactly.
+fn compare(reftext: &str, text: &str) -> Result<(), String> {
+    let reflines: Vec<&str> = reftext.lines().collect();
+    let lines: Vec<&str> = text.lines().collect();
+    if reflines.len() != lines.len() {
+        return Err(format!("reference has {} lines, output has {}", reflines.len(), lines.len()));
+    }
+
+    let mut cursize = "";
+    for ix in 0..lines.len() {
+        let refline = reflines[ix];
+        let line = lines[ix];
+        if line.starts_with("size ") {
+            cursize = line;
+        }
+        if !line.starts_with("frame ") {
+            if line != refline {
+                return Err(format!("line {} differs: {}", ix+1, line));
+            }
+            continue;
+        }
+        let (label, data) = line.rsplit_once(' ').unwrap_or((line, ""));
+        let (reflabel, refdata) = refline.rsplit_once(' ').unwrap_or((refline, ""));
+        if label != reflabel || data.len() != refdata.len() {
+            return Err(format!("{}: {} does not line up with the reference", cursize, label));
+        }
+        let mut worst: Option<(usize, i32)> = None;
+        for jx in (0..data.len()).step_by(2) {
+            let val = i32::from_str_radix(&data[jx..jx+2], 16);
+            let refval = i32::from_str_radix(&refdata[jx..jx+2], 16);
+            let diff = match (val, refval) {
+                (Ok(val), Ok(refval)) => (val - refval).abs(),
+                _ => return Err(format!("{}: {}: bad pixel data", cursize, label)),
+            };
+            if diff > TOLERANCE && worst.is_none_or(|(_, max)| diff > max) {
+                worst = Some((jx / 6, diff));
+            }
+        }
+        if let Some((pixel, diff)) = worst {
+            return Err(format!("{}: {}: pixel {} is off by {}", cursize, label, pixel, diff));
+        }
+    }
+    Ok(())
+}
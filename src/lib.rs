@@ -0,0 +1,49 @@
+// This code is written with explicit returns, field-init, and index loops.
+// Clippy doesn't care for those; don't let it nag.
+#![allow(clippy::needless_return)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::needless_range_loop)]
+
+
+//! The Beacon pattern engine, for embedding. A script is parsed into a
+//! `Script`, wrapped in a `Runner`, and built into a context for a strip
+//! of a given size. Tick the context (by its own clock, or with times you
+//! supply) and read out its pixels.
+//!
+//! ```
+//! use beacon::{parse_script_str, ScriptRunner, RunContext, StripUnits, Pix};
+//!
+//! let units = StripUnits::new(16, 60.0);
+//! let script = parse_script_str("param: wavecycle: sine, period=2", "inline", &units).unwrap();
+//! let runner = ScriptRunner::new(script, "inline");
+//! let mut ctx = runner.build(16, None, Some(1)).unwrap();
+//! ctx.tickat(0.5).unwrap();
+//! let mut pixels = vec![Pix::new(0.0, 0.0, 0.0); 16];
+//! ctx.readpixels(&mut pixels);
+//! ```
+
+pub mod error;
+pub mod pixel;
+pub mod lerp;
+pub mod op;
+pub mod script;
+pub mod parse;
+pub mod param;
+pub mod clock;
+pub mod runner;
+pub mod context;
+pub mod waves;
+pub mod pulser;
+
+pub use error::BeaconError;
+pub use pixel::Pix;
+pub use script::{Script, ScriptMeta};
+pub use parse::{parse_script, parse_script_from, parse_script_str, parse_script_reader};
+pub use parse::source::{ScriptSource, FileSource, MemSource};
+pub use parse::diag::ParseError;
+pub use parse::units::StripUnits;
+pub use runner::{Runner, RunContext, RunContextWrap, PixBuffer};
+pub use context::scriptcontext::ScriptRunner;
+pub use context::limitcontext::LimitRunner;
+pub use context::cyclecontext::CycleRunner;
+pub use context::watchcontext::WatchScriptRunner;
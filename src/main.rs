@@ -1,38 +1,33 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
+// This code is written with explicit returns, field-init, and index loops.
+// Clippy doesn't care for those; don't let it nag.
+#![allow(clippy::needless_return)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::needless_range_loop)]
 
 use gumdrop::Options;
 
 #[cfg(feature = "sdl2")]
 extern crate sdl2; 
 
-#[macro_use]
-extern crate lazy_static;
-
 use std::fs::File;
 use std::io::BufWriter;
 use std::time::Instant;
 use std::time::Duration;
 use std::time::SystemTime;
 
-mod pixel;
-mod lerp;
-mod op;
-mod script;
-mod parse;
-mod param;
-mod clock;
-mod runner;
-mod context;
-mod waves;
-mod pulser;
-
-use script::{Script, ScriptIndex};
-use runner::{Runner, RunContext, PixBuffer};
-use context::scriptcontext::ScriptRunner;
-use context::limitcontext::LimitRunner;
-use context::cyclecontext::CycleRunner;
-use context::watchcontext::WatchScriptRunner;
+mod golden;
+
+use beacon::parse;
+use beacon::error::BeaconError;
+use beacon::script::{Script, ScriptIndex};
+use beacon::parse::units::StripUnits;
+use beacon::runner::{Runner, RunContext, PixBuffer};
+use beacon::context::scriptcontext::ScriptRunner;
+use beacon::context::limitcontext::LimitRunner;
+use beacon::context::cyclecontext::CycleRunner;
+use beacon::context::watchcontext::WatchScriptRunner;
 
 #[derive(Options, Debug)]
 pub struct AppOptions {
@@ -45,12 +40,27 @@ pub struct AppOptions {
     #[options(long="size", help = "pixel count (default 160)")]
     size: Option<usize>,
 
+    #[options(long="ppm", help = "pixels per metre, for scripts that use metres (default 60)")]
+    ppm: Option<f32>,
+
+    #[options(long="seed", help = "random seed, for reproducible runs")]
+    seed: Option<u64>,
+
     #[options(long="fps", help = "frames per second (default 60)")]
     fps: Option<u32>,
 
     #[options(long="dump", help = "dump script to stdout")]
     dump: bool,
 
+    #[options(long="format", help = "rewrite scripts in canonical form")]
+    format: bool,
+
+    #[options(long="golden", help = "check scripts against reference frames in a directory")]
+    golden: Option<String>,
+
+    #[options(long="bless", help = "rewrite reference frames (for --golden)")]
+    bless: bool,
+
     #[options(long="file", help = "run script headless and write to a file (\"file%.png\")")]
     writefile: Option<String>,
 
@@ -83,49 +93,69 @@ pub struct AppOptions {
 fn main() {
     let opts = AppOptions::parse_args_default_or_exit();
 
-    if opts.args.len() == 0 {
+    if opts.args.is_empty() {
         println!("usage: beacon [--dump] script [...]");
         return;
     }
 
-    let pixsize = match opts.size {
-        Some(val) => val,
-        None => 160,
-    };
+    let pixsize = opts.size.unwrap_or(160);
+    let ppm = opts.ppm.unwrap_or(60.0);
+    let units = StripUnits::new(pixsize, ppm);
+
+    if opts.format {
+        let mut failed = false;
+        for filename in &opts.args {
+            match parse::format::format_file(filename, &units) {
+                Ok(true) => println!("{}: formatted", filename),
+                Ok(false) => println!("{}: unchanged", filename),
+                Err(msg) => {
+                    println!("{msg}");
+                    failed = true;
+                },
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(refdir) = &opts.golden {
+        match golden::run_golden(&opts.args, refdir, opts.bless) {
+            Ok(true) => {},
+            Ok(false) => std::process::exit(1),
+            Err(msg) => {
+                println!("{msg}");
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
 
     let mut runners: Vec<Runner> = vec!();
     for filename in &opts.args {
-        let script: Script;
-        
-        match parse::parse_script(&filename) {
-            Ok(val) => {
-                script = val;
-            },
+        let script: Script = match parse::parse_script(filename, &units) {
+            Ok(val) => val,
             Err(msg) => {
                 println!("{msg}");
                 return;
             },
-        }
+        };
         
         if opts.dump {
             script.dump();
-            let res = script.consistency_check();
-            match res {
-                Err(msg) => {
-                    println!("{msg}");
-                },
-                Ok(()) => {},
+            if let Err(msg) = script.consistency_check() {
+                println!("{msg}");
             }
             return;
         }
 
-        let runner: Runner;
-        if opts.watchfile {
-            runner = WatchScriptRunner::new(&filename, script);
+        let runner: Runner = if opts.watchfile {
+            WatchScriptRunner::new(filename, script, ppm)
         }
         else {
-            runner = ScriptRunner::new(script, &filename);
-        }
+            ScriptRunner::new(script, filename)
+        };
 
         runners.push(runner);
     }
@@ -140,10 +170,14 @@ fn main() {
     let fps = opts.fps.unwrap_or(60);
 
     if let Some(filename) = &opts.writefile {
-        let framecount = opts.framecount.unwrap_or(16);
-        let frameskip = opts.frameskip.unwrap_or(0);
-        let pixheight = opts.winheight.unwrap_or(4) as usize;
-        let res = run_writefile(filename, runner, pixsize, pixheight, fps, framecount, frameskip);
+        let spec = WriteFileSpec {
+            filename: filename,
+            pixheight: opts.winheight.unwrap_or(4) as usize,
+            framecount: opts.framecount.unwrap_or(16),
+            frameskip: opts.frameskip.unwrap_or(0),
+        };
+        let framecount = spec.framecount;
+        let res = run_writefile(&spec, runner, pixsize, fps, opts.seed);
         match res {
             Err(msg) => {
                 println!("{msg}");
@@ -155,7 +189,7 @@ fn main() {
     }
     else if opts.spin {
         let dur: f64 = 0.1;
-        let res = run_spin(runner, pixsize, fps, dur);
+        let res = run_spin(runner, pixsize, fps, opts.seed, dur);
         match res {
             Err(msg) => {
                 println!("{msg}");
@@ -166,7 +200,7 @@ fn main() {
         }
     }
     else if opts.led {
-        let res = run_leds(runner, pixsize, fps);
+        let res = run_leds(runner, pixsize, fps, opts.seed);
         if let Err(msg) = res {
             println!("{msg}");
         }
@@ -174,15 +208,15 @@ fn main() {
     else {
         let winwidth = opts.winwidth.unwrap_or(800);
         let winheight = opts.winheight.unwrap_or(100);
-        let res = run_sdl(runner, pixsize, fps, opts.showpower, winwidth, winheight);
+        let res = run_sdl(runner, pixsize, fps, opts.seed, opts.showpower, winwidth, winheight);
         if let Err(msg) = res {
             println!("{msg}");
         }
     }
 }
 
-fn run_spin(runner: Runner, pixsize: usize, fps: u32, seconds: f64) -> Result<usize, String> {
-    let mut ctx = runner.build(pixsize, Some(fps))?;
+fn run_spin(runner: Runner, pixsize: usize, fps: u32, seed: Option<u64>, seconds: f64) -> Result<usize, BeaconError> {
+    let mut ctx = runner.build(pixsize, Some(fps), seed)?;
     let mut count = 0;
     let start = Instant::now();
     
@@ -194,27 +228,37 @@ fn run_spin(runner: Runner, pixsize: usize, fps: u32, seconds: f64) -> Result<us
             break;
         }
         if ctx.done() {
-            return Err("script ended before time".to_string());
+            return Err(BeaconError::runtime(&None, "script ended before time".to_string()));
         }
     }
     
     Ok(count)
 }
 
+// The images to write for --writefile. A "%" in the filename is replaced
+// by the frame number.
+struct WriteFileSpec<'a> {
+    filename: &'a str,
+    pixheight: usize,
+    framecount: usize,
+    frameskip: usize,
+}
+
 #[cfg(not(feature = "png"))]
-fn run_writefile(_filename: &str, _runner: Runner, _pixsize: usize, _pixheight: usize, _fps: u32, _framecount: usize, _frameskip: usize) -> Result<(), String> {
-    return Err("png feature not available".to_string());
+fn run_writefile(_spec: &WriteFileSpec, _runner: Runner, _pixsize: usize, _fps: u32, _seed: Option<u64>) -> Result<(), BeaconError> {
+    return Err(BeaconError::output("png feature not available".to_string()));
 }
 
 #[cfg(feature = "png")]
-fn run_writefile(filename: &str, runner: Runner, pixsize: usize, pixheight: usize, fps: u32, framecount: usize, frameskip: usize) -> Result<(), String> {
-    let mut ctx = runner.build(pixsize, Some(fps))?;
+fn run_writefile(spec: &WriteFileSpec, runner: Runner, pixsize: usize, fps: u32, seed: Option<u64>) -> Result<(), BeaconError> {
+    let mut ctx = runner.build(pixsize, Some(fps), seed)?;
+    let pixheight = spec.pixheight;
 
-    for _ in 0..frameskip {
+    for _ in 0..spec.frameskip {
         ctx.tick()?;
     }
 
-    for count in 0..framecount {
+    for count in 0..spec.framecount {
         ctx.tick()?;
         if ctx.done() {
             break;
@@ -248,28 +292,28 @@ fn run_writefile(filename: &str, runner: Runner, pixsize: usize, pixheight: usiz
             }
         });
         
-        let tempfile = filename.replace("%", &format!("{:04}", count).to_string());
-        let file = File::create(tempfile)
-            .map_err(|err| err.to_string())?;
+        let tempfile = spec.filename.replace("%", &format!("{:04}", count).to_string());
+        let file = File::create(&tempfile)
+            .map_err(|err| BeaconError::output(format!("{}: {}", tempfile, err)))?;
         let ref mut fwriter = BufWriter::new(file);
         let mut encoder = png::Encoder::new(fwriter, pixsize as u32, pixheight as u32);
         encoder.set_color(png::ColorType::Rgba);
         encoder.set_depth(png::BitDepth::Eight);
         let mut writer = encoder.write_header().unwrap();
         writer.write_image_data(&buffer)
-            .map_err(|err| err.to_string())?;
+            .map_err(|err| BeaconError::output(err.to_string()))?;
     }
 
     Ok(())
 }
 
 #[cfg(not(feature = "rpi"))]
-fn run_leds(_runner: Runner, _pixsize: usize, _fps: u32) -> Result<(), String> {
-    return Err("rpi feature not available".to_string());
+fn run_leds(_runner: Runner, _pixsize: usize, _fps: u32, _seed: Option<u64>) -> Result<(), BeaconError> {
+    return Err(BeaconError::output("rpi feature not available".to_string()));
 }
 
 #[cfg(feature = "rpi")]
-fn run_leds(runner: Runner, pixsize: usize, fps: u32) -> Result<(), String> {
+fn run_leds(runner: Runner, pixsize: usize, fps: u32, seed: Option<u64>) -> Result<(), BeaconError> {
     use rppal::spi::{Bus, SlaveSelect, Spi};
     use smart_leds_trait::{RGB8, SmartLedsWrite};
 
@@ -277,7 +321,7 @@ fn run_leds(runner: Runner, pixsize: usize, fps: u32) -> Result<(), String> {
     
     //###
     let info = rppal::system::DeviceInfo::new()
-        .map_err(|err| err.to_string())?;
+        .map_err(|err| BeaconError::output(err.to_string()))?;
     println!("### info {:?}", info);
 
     let spi = Spi::new(
@@ -291,13 +335,13 @@ fn run_leds(runner: Runner, pixsize: usize, fps: u32) -> Result<(), String> {
         20_000_000, // Hz?
         // Mode0: IdleLow (CPOL0), CaptureOnFirstTransition (CPHA0)
         rppal::spi::Mode::Mode0)
-        .map_err(|err| err.to_string())?;
+        .map_err(|err| BeaconError::output(err.to_string()))?;
     println!("### spi {:?}", spi);
 
     let mut driver = apa102_spi::Apa102::new(spi);
     //### might need to change default BGR
 
-    let mut ctx = runner.build(pixsize, None)?;
+    let mut ctx = runner.build(pixsize, None, seed)?;
     
     loop {
         ctx.tick()?;
@@ -321,7 +365,7 @@ fn run_leds(runner: Runner, pixsize: usize, fps: u32) -> Result<(), String> {
 
         //### apply gamma and brightness limiter?
         driver.write(buffer)
-            .map_err(|err| err.to_string())?;
+            .map_err(|err| BeaconError::output(err.to_string()))?;
 
         if ctx.done() {
             break;
@@ -335,12 +379,12 @@ fn run_leds(runner: Runner, pixsize: usize, fps: u32) -> Result<(), String> {
 }
 
 #[cfg(not(feature = "sdl2"))]
-fn run_sdl(_runner: Runner, _pixsize: usize, _fps: u32, _filename: &str, _showpower: bool, _winwidth: u32, _winheight: u32) -> Result<(), String> {
-    return Err("sdl2 feature not available".to_string());
+fn run_sdl(_runner: Runner, _pixsize: usize, _fps: u32, _seed: Option<u64>, _showpower: bool, _winwidth: u32, _winheight: u32) -> Result<(), BeaconError> {
+    return Err(BeaconError::output("sdl2 feature not available".to_string()));
 }
 
 #[cfg(feature = "sdl2")]
-fn run_sdl(runner: Runner, pixsize: usize, fps: u32, showpower: bool, winwidth: u32, winheight: u32) -> Result<(), String> {
+fn run_sdl(runner: Runner, pixsize: usize, fps: u32, seed: Option<u64>, showpower: bool, winwidth: u32, winheight: u32) -> Result<(), BeaconError> {
     use sdl2::pixels::Color;
     use sdl2::event::Event;
     use sdl2::keyboard::Keycode;
@@ -351,8 +395,10 @@ fn run_sdl(runner: Runner, pixsize: usize, fps: u32, showpower: bool, winwidth:
     let margin: u32 = 16;
     let copyrect = sdl2::rect::Rect::new(0, margin as i32, winwidth, winheight);
     
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
+    let sdl_context = sdl2::init()
+        .map_err(BeaconError::output)?;
+    let video_subsystem = sdl_context.video()
+        .map_err(BeaconError::output)?;
 
     let mut powertime: f64 = 0.0;
  
@@ -360,23 +406,24 @@ fn run_sdl(runner: Runner, pixsize: usize, fps: u32, showpower: bool, winwidth:
     let window = video_subsystem.window(wintitle.as_str(), winwidth, winheight+2*margin)
         .position_centered()
         .build()
-        .map_err(|err| err.to_string())?;
+        .map_err(|err| BeaconError::output(err.to_string()))?;
 
     let mut canvas = window.into_canvas()
         .present_vsync()
         .build()
-        .map_err(|err| err.to_string())?;
+        .map_err(|err| BeaconError::output(err.to_string()))?;
     let tc = canvas.texture_creator();
     let mut texture = tc.create_texture_streaming(PixelFormatEnum::RGB24, pixsize as u32, 1)
-        .map_err(|err| err.to_string())?;
+        .map_err(|err| BeaconError::output(err.to_string()))?;
  
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
     canvas.present();
     
-    let mut event_pump = sdl_context.event_pump()?;
+    let mut event_pump = sdl_context.event_pump()
+        .map_err(BeaconError::output)?;
 
-    let mut ctx = runner.build(pixsize, None)?;
+    let mut ctx = runner.build(pixsize, None, seed)?;
     let mut pause = false;
         
     'running: loop {
@@ -428,9 +475,10 @@ fn run_sdl(runner: Runner, pixsize: usize, fps: u32, showpower: bool, winwidth:
                     }
                 }
             })
-        })?;
+        }).map_err(BeaconError::output)?;
         canvas.clear();
-        canvas.copy(&texture, None, Some(copyrect))?;
+        canvas.copy(&texture, None, Some(copyrect))
+            .map_err(BeaconError::output)?;
 
         if ctx.done() {
             break Ok(())
@@ -1,15 +1,16 @@
 use std::fmt;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use rand::Rng;
 
 use crate::context::scriptcontext::ScriptContext;
 use crate::runner::RunContext;
 use crate::lerp::Lerp;
 use crate::pixel::Pix;
+use crate::error::BeaconError;
 use crate::waves::WaveShape;
 use crate::param::Param;
 use crate::pulser::{Pulser, PulserState};
-use crate::script::ScriptIndex;
+use crate::parse::diag::SourceLoc;
 
 #[derive(Clone)]
 pub enum Op1Def {
@@ -29,9 +30,22 @@ pub enum Op1Def {
     Min(), // op1...
     Max(), // op1...
     Clamp(Param, Param), // min, max; op1
+    Sub(), // op1, op1
+    Div(), // op1, op1
+    AbsDiff(), // op1, op1
+    Pow(), // op1, op1
+    Mod(), // op1, op1
+    Step(), // op1, edge
+    SmoothStep(), // op1, edge0, edge1
+    Remap(), // op1, inmin, inmax, outmin, outmax
     Shift(Param), // offset; op1
     ShiftDecay(Param, Param), // offset, halflife; op1
     Noise(usize, usize, Param, Param), // grain, octaves, offset, max
+    Blur(BlurKernel, EdgeMode, Option<Param>), // kernel, edge, radius; op1
+    Mirror(Param, EdgeMode), // center, edge; op1
+    Reverse(), // op1
+    Tile(Param), // count; op1
+    Zoom(Param, Param, EdgeMode), // scale, center, edge; op1
 }
 
 #[derive(Clone)]
@@ -52,10 +66,53 @@ pub enum Op3Def {
     Max(), // op3...
     Lerp(), // op3, op3, op1
     Mask(Param), // op3, op3, op1
+    Blend(BlendMode, Param), // mode, opacity; op3...
     Shift(Param), // offset; op3
+    Blur(BlurKernel, EdgeMode, Option<Param>), // kernel, edge, radius; op3
+    Mirror(Param, EdgeMode), // center, edge; op3
+    Reverse(), // op3
+    Tile(Param), // count; op3
+    Zoom(Param, Param, EdgeMode), // scale, center, edge; op3
+}
+
+// An op which produces NaN or infinite values warns about it at most
+// this often (in seconds).
+const WARNINTERVAL: f64 = 5.0;
+
+// Reject an op if a param can never be in range. This goes by the
+// param's bounds at the start; a param that might be fine is let
+// through, and the tick sanitises anything that goes wrong later.
+// Returns the name of the bad param.
+fn check_positive(params: Vec<(&'static str, &Param)>) -> Result<(), &'static str> {
+    for (name, param) in params {
+        if let Some(max) = param.max(0.0) {
+            if max <= 0.0 || max.is_nan() {
+                return Err(name);
+            }
+        }
+    }
+    Ok(())
 }
 
 impl Op1Def {
+    // Params which must be positive, or the op divides by zero.
+    fn positive_params(&self) -> Vec<(&'static str, &Param)> {
+        match self {
+            Op1Def::Wave(_shape, _min, _max, _pos, width) => vec![("width", width)],
+            Op1Def::WaveCycle(_shape, _min, _max, _pos, period) => vec![("period", period)],
+            Op1Def::Decay(halflife) => vec![("halflife", halflife)],
+            Op1Def::ShiftDecay(_offset, halflife) => vec![("halflife", halflife)],
+            Op1Def::Pulser(pulser) => pulser.positive_params(),
+            Op1Def::Tile(count) => vec![("count", count)],
+            Op1Def::Zoom(scale, _center, _edge) => vec![("scale", scale)],
+            _ => vec![],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        check_positive(self.positive_params())
+    }
+
     pub fn describe(&self, indent: Option<String>) -> String {
         match self {
             Op1Def::Constant(val) => {
@@ -71,7 +128,7 @@ impl Op1Def {
                 format!("Wave({:?}, min={:?}, max={:?}, pos={:?}, period={:?})", shape, min, max, pos, period)
             },
             Op1Def::Invert() => {
-                format!("Invert()")
+                "Invert()".to_string()
             },
             Op1Def::Pulser(pulser) => {
                 let limitstr = if let Some(size) = pulser.countlimit {
@@ -97,36 +154,75 @@ impl Op1Def {
                 format!("Decay({:?})", halflife)
             },
             Op1Def::TimeDelta() => {
-                format!("TimeDelta()")
+                "TimeDelta()".to_string()
             },
             Op1Def::Brightness() => {
-                format!("Brightness()")
+                "Brightness()".to_string()
             },
             Op1Def::Gradient(stops) => {
                 let stopstrs = stops.iter().map(|stop| stop.to_string()).collect::<Vec<_>>();
                 format!("Gradient({})", stopstrs.join(", "))
             },
             Op1Def::Mul() => {
-                format!("Mul()")
+                "Mul()".to_string()
             },
             Op1Def::Sum() => {
-                format!("Sum()")
+                "Sum()".to_string()
             },
             Op1Def::Mean() => {
-                format!("Mean()")
+                "Mean()".to_string()
             },
             Op1Def::Min() => {
-                format!("Min()")
+                "Min()".to_string()
             },
             Op1Def::Max() => {
-                format!("Max()")
+                "Max()".to_string()
             },
             Op1Def::Clamp(min, max) => {
                 format!("Clamp({:?}, {:?})", min, max)
             },
+            Op1Def::Sub() => {
+                "Sub()".to_string()
+            },
+            Op1Def::Div() => {
+                "Div()".to_string()
+            },
+            Op1Def::AbsDiff() => {
+                "AbsDiff()".to_string()
+            },
+            Op1Def::Pow() => {
+                "Pow()".to_string()
+            },
+            Op1Def::Mod() => {
+                "Mod()".to_string()
+            },
+            Op1Def::Step() => {
+                "Step()".to_string()
+            },
+            Op1Def::SmoothStep() => {
+                "SmoothStep()".to_string()
+            },
+            Op1Def::Remap() => {
+                "Remap()".to_string()
+            },
             Op1Def::Shift(offset) => {
                 format!("Shift({:?})", offset)
             },
+            Op1Def::Blur(kernel, edge, radius) => {
+                format!("Blur({:?}, edge={:?}, radius={:?})", kernel, edge, radius)
+            },
+            Op1Def::Mirror(center, edge) => {
+                format!("Mirror(center={:?}, edge={:?})", center, edge)
+            },
+            Op1Def::Reverse() => {
+                "Reverse()".to_string()
+            },
+            Op1Def::Tile(count) => {
+                format!("Tile({:?})", count)
+            },
+            Op1Def::Zoom(scale, center, edge) => {
+                format!("Zoom(scale={:?}, center={:?}, edge={:?})", scale, center, edge)
+            },
             Op1Def::ShiftDecay(offset, halflife) => {
                 format!("ShiftDecay(offset={:?}, halflife={:?})", offset, halflife)
             },
@@ -139,28 +235,45 @@ impl Op1Def {
 }
 
 impl Op3Def {
+    fn positive_params(&self) -> Vec<(&'static str, &Param)> {
+        match self {
+            Op3Def::Tile(count) => vec![("count", count)],
+            Op3Def::Zoom(scale, _center, _edge) => vec![("scale", scale)],
+            _ => vec![],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        check_positive(self.positive_params())
+    }
+
     pub fn describe(&self, _indent: Option<String>) -> String {
         match self {
             Op3Def::Constant(pix) => {
-                format!("Constant(r={}, g={}, b={})", pix.r, pix.g, pix.b)
+                if pix.a >= 1.0 {
+                    format!("Constant(r={}, g={}, b={})", pix.r, pix.g, pix.b)
+                }
+                else {
+                    format!("Constant(r={}, g={}, b={}, a={})", pix.r, pix.g, pix.b, pix.a)
+                }
             },
             Op3Def::Invert() => {
-                format!("Invert()")
+                "Invert()".to_string()
             },
             Op3Def::Grey() => {
-                format!("Grey()")
+                "Grey()".to_string()
             },
             Op3Def::RGB() => {
-                format!("RGB()")
+                "RGB()".to_string()
             },
             Op3Def::HSV() => {
-                format!("HSV()")
+                "HSV()".to_string()
             },
             Op3Def::HSVToRGB() => {
-                format!("HSVToRGB()")
+                "HSVToRGB()".to_string()
             },
             Op3Def::RGBToHSV() => {
-                format!("RGBToHSV()")
+                "RGBToHSV()".to_string()
             },
             Op3Def::Gradient(stops) => {
                 let stopstrs = stops.iter().map(|stop| stop.as_hex()).collect::<Vec<_>>();
@@ -171,29 +284,47 @@ impl Op3Def {
                 format!("PGradient({})", stopstrs.join(", "))
             },
             Op3Def::MulS() => {
-                format!("MulS()")
+                "MulS()".to_string()
             },
             Op3Def::Sum() => {
-                format!("Sum()")
+                "Sum()".to_string()
             },
             Op3Def::Mean() => {
-                format!("Mean()")
+                "Mean()".to_string()
             },
             Op3Def::Min() => {
-                format!("Min()")
+                "Min()".to_string()
             },
             Op3Def::Max() => {
-                format!("Max()")
+                "Max()".to_string()
             },
             Op3Def::Lerp() => {
-                format!("Lerp()")
+                "Lerp()".to_string()
             },
             Op3Def::Mask(threshold) => {
                 format!("Mask({:?})", threshold)
             },
+            Op3Def::Blend(mode, opacity) => {
+                format!("Blend({:?}, opacity={:?})", mode, opacity)
+            },
             Op3Def::Shift(offset) => {
                 format!("Shift({:?})", offset)
             },
+            Op3Def::Blur(kernel, edge, radius) => {
+                format!("Blur({:?}, edge={:?}, radius={:?})", kernel, edge, radius)
+            },
+            Op3Def::Mirror(center, edge) => {
+                format!("Mirror(center={:?}, edge={:?})", center, edge)
+            },
+            Op3Def::Reverse() => {
+                "Reverse()".to_string()
+            },
+            Op3Def::Tile(count) => {
+                format!("Tile({:?})", count)
+            },
+            Op3Def::Zoom(scale, center, edge) => {
+                format!("Zoom(scale={:?}, center={:?}, edge={:?})", scale, center, edge)
+            },
             //_ => "?Op3Def".to_string(),
         }
     }
@@ -217,20 +348,24 @@ pub enum Op1State {
     Decay(Vec<f32>),
     TimeDelta(Vec<f32>),
     Noise(NoiseState),
+    Blur(BlurState),
 }
 
 pub enum Op3State {
     NoState,
+    Blur(BlurState),
 }
 
 pub struct Op1Ctx {
     pub state: RefCell<Op1State>,
     pub buf: RefCell<Vec<f32>>,
+    pub lastwarn: Cell<Option<f64>>, // when this op last warned about bad values
 }
 
 pub struct Op3Ctx {
     pub state: RefCell<Op3State>,
     pub buf: RefCell<Vec<Pix<f32>>>,
+    pub lastwarn: Cell<Option<f64>>,
 }
 
 pub struct NoiseState {
@@ -238,12 +373,255 @@ pub struct NoiseState {
     fudgemax: f32,
 }
 
+// The last kernel a blur built, kept until its radius changes.
+pub struct BlurState {
+    radius: Option<f32>,
+    weights: Vec<f32>,
+}
+
 #[derive(Clone, Debug)]
 pub struct GradStop {
     pub pos: f32,
     pub color: Pix<f32>,
 }
 
+// The weights a blur spreads over its radius.
+#[derive(Clone, Debug)]
+pub enum BlurKernel {
+    Box,
+    Gaussian,
+    Custom(Vec<f32>), // evenly spaced across the radius, both ways
+}
+
+// What a blur reads beyond the ends of the strip.
+#[derive(Clone, Copy, Debug)]
+pub enum EdgeMode {
+    Wrap,  // the other end, as Shift does
+    Clamp, // the end pixel
+    Zero,
+}
+
+// How a blend lays each layer onto the ones below it.
+#[derive(Clone, Copy, Debug)]
+pub enum BlendMode {
+    Screen,
+    Multiply,
+    Overlay,
+    Add, // clamped at 1
+    Subtract, // clamped at 0
+    Difference,
+    Lighten,
+    Darken,
+    Over, // the layer covers what's below, as far as it's opaque
+}
+
+impl BlendMode {
+    pub fn apply(&self, base: f32, layer: f32) -> f32 {
+        match self {
+            BlendMode::Screen => 1.0 - (1.0 - base) * (1.0 - layer),
+            BlendMode::Multiply => base * layer,
+            BlendMode::Overlay => {
+                if base < 0.5 {
+                    2.0 * base * layer
+                }
+                else {
+                    1.0 - 2.0 * (1.0 - base) * (1.0 - layer)
+                }
+            },
+            BlendMode::Add => (base + layer).min(1.0),
+            BlendMode::Subtract => (base - layer).max(0.0),
+            BlendMode::Difference => (base - layer).abs(),
+            BlendMode::Lighten => base.max(layer),
+            BlendMode::Darken => base.min(layer),
+            BlendMode::Over => layer,
+        }
+    }
+
+    // Lay one (premultiplied) pixel on another. Where both are opaque
+    // this is just apply(); where either is transparent, the other
+    // shows through.
+    pub fn composite(&self, base: &Pix<f32>, layer: &Pix<f32>) -> Pix<f32> {
+        let (baser, baseg, baseb) = base.unpremultiplied();
+        let (layerr, layerg, layerb) = layer.unpremultiplied();
+        let both = base.a * layer.a;
+        Pix {
+            r: layer.r * (1.0 - base.a) + base.r * (1.0 - layer.a) + both * self.apply(baser, layerr),
+            g: layer.g * (1.0 - base.a) + base.g * (1.0 - layer.a) + both * self.apply(baseg, layerg),
+            b: layer.b * (1.0 - base.a) + base.b * (1.0 - layer.a) + both * self.apply(baseb, layerb),
+            a: layer.a + base.a * (1.0 - layer.a),
+        }
+    }
+}
+
+impl BlurKernel {
+    // Weights for offsets -n to n, where the radius is in pixels. None
+    // means the kernel's own size: one pixel for box and gaussian, one
+    // pixel per weight for custom. The weights sum to 1, unless they
+    // sum to nothing (as an edge-finding kernel does).
+    pub fn weights(&self, radius: Option<f32>) -> Vec<f32> {
+        let mut res: Vec<f32>;
+        match (self, radius) {
+            (BlurKernel::Custom(vals), None) => {
+                res = vals.clone();
+            },
+            (BlurKernel::Box, radius) => {
+                // Fractional radii fade the end pixels in.
+                let radius = radius.unwrap_or(1.0).max(0.0);
+                let span = (radius + 0.5).ceil() as i32;
+                res = (-span..=span).map(|off| (radius + 0.5 - off.abs() as f32).clamp(0.0, 1.0)).collect();
+            },
+            (BlurKernel::Gaussian, radius) => {
+                // The radius is three standard deviations.
+                let radius = radius.unwrap_or(1.0).max(0.0);
+                let span = radius.ceil() as i32;
+                let sigma = (radius / 3.0).max(0.01);
+                res = (-span..=span).map(|off| (-0.5 * (off as f32 / sigma).powi(2)).exp()).collect();
+            },
+            (BlurKernel::Custom(vals), Some(radius)) => {
+                let radius = radius.max(0.0);
+                let span = radius.ceil() as i32;
+                let last = (vals.len() - 1) as f32;
+                res = (-span..=span).map(|off| {
+                    if vals.len() == 1 || radius == 0.0 {
+                        return vals[vals.len() / 2];
+                    }
+                    let pos = (off as f32 / radius + 1.0) * 0.5 * last;
+                    let seg = pos.floor() as usize;
+                    if seg >= vals.len() - 1 {
+                        return vals[vals.len() - 1];
+                    }
+                    vals[seg].lerp(&vals[seg+1], &(pos - seg as f32))
+                }).collect();
+            },
+        }
+        let total: f32 = res.iter().sum();
+        if total.abs() > 1.0e-6 {
+            for val in res.iter_mut() {
+                *val /= total;
+            }
+        }
+        res
+    }
+}
+
+impl EdgeMode {
+    // The buffer index to read for a position, which may be off the strip.
+    pub fn index(&self, pos: i32, buflen: i32) -> Option<usize> {
+        if pos >= 0 && pos < buflen {
+            return Some(pos as usize);
+        }
+        match self {
+            EdgeMode::Wrap => Some(pos.rem_euclid(buflen) as usize),
+            EdgeMode::Clamp => Some(pos.clamp(0, buflen-1) as usize),
+            EdgeMode::Zero => None,
+        }
+    }
+}
+
+// Where a spatial transform reads for one pixel: two neighbours and the
+// fraction between them. None is off the strip (under EdgeMode::Zero).
+pub struct Tap {
+    lo: Option<usize>,
+    hi: Option<usize>,
+    frac: f32,
+}
+
+// Taps for every pixel, given a function from a pixel's position to
+// where it reads from. Positions are pixel centers, so that 0.5 is the
+// middle of the strip however many pixels there are.
+fn transform_taps<F>(buflen: usize, edge: EdgeMode, func: F) -> Vec<Tap>
+where F: Fn(f32) -> f32 {
+    let buflen32 = buflen as f32;
+    let mut res: Vec<Tap> = Vec::with_capacity(buflen);
+    for ix in 0..buflen {
+        let pos = func((ix as f32 + 0.5) / buflen32) * buflen32 - 0.5;
+        let seg = pos.floor();
+        res.push(Tap {
+            lo: edge.index(seg as i32, buflen as i32),
+            hi: edge.index(seg as i32 + 1, buflen as i32),
+            frac: pos - seg,
+        });
+    }
+    res
+}
+
+// The taps for a transform op, or None if it isn't one.
+fn transform_op1_taps(op: &Op1Def, ctx: &ScriptContext, buflen: usize) -> Option<Vec<Tap>> {
+    let age = ctx.age() as f32;
+    match op {
+        Op1Def::Mirror(center, edge) => Some(mirror_taps(center.eval(ctx, age), *edge, buflen)),
+        Op1Def::Reverse() => Some(transform_taps(buflen, EdgeMode::Wrap, |pos| 1.0 - pos)),
+        Op1Def::Tile(count) => Some(tile_taps(count.eval(ctx, age), buflen)),
+        Op1Def::Zoom(scale, center, edge) => Some(zoom_taps(scale.eval(ctx, age), center.eval(ctx, age), *edge, buflen)),
+        _ => None,
+    }
+}
+
+fn transform_op3_taps(op: &Op3Def, ctx: &ScriptContext, buflen: usize) -> Option<Vec<Tap>> {
+    let age = ctx.age() as f32;
+    match op {
+        Op3Def::Mirror(center, edge) => Some(mirror_taps(center.eval(ctx, age), *edge, buflen)),
+        Op3Def::Reverse() => Some(transform_taps(buflen, EdgeMode::Wrap, |pos| 1.0 - pos)),
+        Op3Def::Tile(count) => Some(tile_taps(count.eval(ctx, age), buflen)),
+        Op3Def::Zoom(scale, center, edge) => Some(zoom_taps(scale.eval(ctx, age), center.eval(ctx, age), *edge, buflen)),
+        _ => None,
+    }
+}
+
+// The side below the center is kept, and reflected onto the side above.
+fn mirror_taps(center: f32, edge: EdgeMode, buflen: usize) -> Vec<Tap> {
+    transform_taps(buflen, edge, |pos| center - (pos - center).abs())
+}
+
+fn tile_taps(count: f32, buflen: usize) -> Vec<Tap> {
+    transform_taps(buflen, EdgeMode::Wrap, |pos| (pos * count).rem_euclid(1.0))
+}
+
+// A scale above 1 magnifies.
+fn zoom_taps(scale: f32, center: f32, edge: EdgeMode, buflen: usize) -> Vec<Tap> {
+    transform_taps(buflen, edge, |pos| center + (pos - center) / scale)
+}
+
+// The blur radius in pixels, if one was given. Past the length of the
+// strip, a wider kernel would only read the same pixels again, so it's
+// capped there.
+fn blur_radius(radius: &Option<Param>, ctx: &ScriptContext, buflen: usize) -> Result<Option<f32>, String> {
+    let age = ctx.age() as f32;
+    match radius {
+        None => Ok(None),
+        Some(radius) => {
+            let val = radius.eval(ctx, age) * buflen as f32;
+            if !val.is_finite() {
+                return Err(format!("blur radius is not finite: {}", val));
+            }
+            Ok(Some(val.min(buflen as f32)))
+        },
+    }
+}
+
+impl Default for BlurState {
+    fn default() -> BlurState {
+        BlurState::new()
+    }
+}
+
+impl BlurState {
+    pub fn new() -> BlurState {
+        BlurState {
+            radius: None,
+            weights: Vec::default(),
+        }
+    }
+
+    pub fn weights(&mut self, kernel: &BlurKernel, radius: Option<f32>) -> &Vec<f32> {
+        if self.weights.is_empty() || self.radius != radius {
+            self.weights = kernel.weights(radius);
+            self.radius = radius;
+        }
+        &self.weights
+    }
+}
+
 impl NoiseState {
     pub fn new(grain: usize, octaves: usize, ctx: &mut ScriptContext) -> NoiseState {
         let mut res = NoiseState {
@@ -286,6 +664,7 @@ impl Op1State {
             Op1Def::ShiftDecay(_offset, _halflife) => Op1State::Decay(vec![0.0; ctx.size()]),
             Op1Def::TimeDelta() => Op1State::TimeDelta(vec![0.0; ctx.size()]),
             Op1Def::Noise(grain, octaves, _offset, _max) => Op1State::Noise(NoiseState::new(*grain, *octaves, ctx)),
+            Op1Def::Blur(..) => Op1State::Blur(BlurState::new()),
             _ => Op1State::NoState,
         }
     }
@@ -294,13 +673,48 @@ impl Op1State {
 impl Op3State {
     pub fn new_for(op: &Op3Def, _ctx: &mut ScriptContext) -> Op3State {
         match op {
+            Op3Def::Blur(..) => Op3State::Blur(BlurState::new()),
             _ => Op3State::NoState,
         }
     }
 }
 
+// Say (on stderr) that an op produced bad values, unless it said so
+// recently.
+fn warn_nonfinite(ctx: &ScriptContext, lastwarn: &Cell<Option<f64>>, desc: String, loc: &Option<SourceLoc>, count: usize) {
+    let age = ctx.age();
+    if let Some(last) = lastwarn.get() {
+        if age >= last && age < last + WARNINTERVAL {
+            return;
+        }
+    }
+    lastwarn.set(Some(age));
+    let name = desc.split('(').next().unwrap_or("op").to_string();
+    match loc {
+        Some(loc) => eprintln!("warning: {} ({}:{}:{}) produced {} NaN or infinite values; replaced with 0", name, loc.file, loc.line, loc.column+1, count),
+        None => eprintln!("warning: {} produced {} NaN or infinite values; replaced with 0", name, count),
+    }
+}
+
 impl Op1Ctx {
-    pub fn tickop(ctx: &mut ScriptContext, bufnum: usize) {
+    // Zero out any NaN or infinite values left by the op, so that they
+    // don't spread to everything downstream.
+    pub fn sanitize(ctx: &ScriptContext, bufnum: usize) {
+        let mut buf = ctx.op1s[bufnum].buf.borrow_mut();
+        let mut count = 0;
+        for ix in 0..buf.len() {
+            if !buf[ix].is_finite() {
+                buf[ix] = 0.0;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            let opref = &ctx.script.op1s[bufnum];
+            warn_nonfinite(ctx, &ctx.op1s[bufnum].lastwarn, opref.op.describe(None), &opref.loc, count);
+        }
+    }
+
+    pub fn tickop(ctx: &mut ScriptContext, bufnum: usize) -> Result<(), BeaconError> {
         let opref = &ctx.script.op1s[bufnum];
         let mut buf = ctx.op1s[bufnum].buf.borrow_mut();
         match &opref.op {
@@ -345,7 +759,7 @@ impl Op1Ctx {
             }
 
             Op1Def::Invert() => {
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -356,11 +770,11 @@ impl Op1Ctx {
             Op1Def::Pulser(pulser) => {
                 let mut state = ctx.op1s[bufnum].state.borrow_mut();
                 if let Op1State::Pulser(pstate) = &mut *state {
-                    pstate.tick(ctx, &pulser);
+                    pstate.tick(ctx, pulser);
                     pstate.render(ctx, &mut buf);
                 }
                 else {
-                    panic!("Op1 state mismatch: PulserState");
+                    return Err(opref.err("Op1 state mismatch: PulserState".to_string()));
                 }
             }
 
@@ -368,7 +782,7 @@ impl Op1Ctx {
                 let age = ctx.age() as f32;
                 let halflife = halflife.eval(ctx, age);
                 let decaymul = (2.0_f32).powf(-ctx.ticklen()/halflife);
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 let mut state = ctx.op1s[bufnum].state.borrow_mut();
                 if let Op1State::Decay(historybuf) = &mut *state {
@@ -381,12 +795,12 @@ impl Op1Ctx {
                     }
                 }
                 else {
-                    panic!("Op1 state mismatch: Decay");
+                    return Err(opref.err("Op1 state mismatch: Decay".to_string()));
                 }
             }
 
             Op1Def::TimeDelta() => {
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 let mut state = ctx.op1s[bufnum].state.borrow_mut();
                 if let Op1State::TimeDelta(historybuf) = &mut *state {
@@ -399,12 +813,12 @@ impl Op1Ctx {
                     }
                 }
                 else {
-                    panic!("Op1 state mismatch: TimeDelta");
+                    return Err(opref.err("Op1 state mismatch: TimeDelta".to_string()));
                 }
             }
 
             Op1Def::Gradient(stops) => {
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 let count = stops.len();
@@ -439,8 +853,8 @@ impl Op1Ctx {
             }
 
             Op1Def::Mul() => {
-                let obufnum1 = opref.get_type_ref(1, 0);
-                let obufnum2 = opref.get_type_ref(1, 1);
+                let obufnum1 = opref.get_type_ref(1, 0)?;
+                let obufnum2 = opref.get_type_ref(1, 1)?;
                 let obuf1 = ctx.op1s[obufnum1].buf.borrow();
                 let obuf2 = ctx.op1s[obufnum2].buf.borrow();
                 assert!(buf.len() == obuf1.len());
@@ -451,19 +865,19 @@ impl Op1Ctx {
             }
             
             Op1Def::Sum() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = 0.0;
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(1, jx);
+                        let obufnum = opref.get_type_ref(1, jx)?;
                         let obuf = ctx.op1s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix] += obuf[ix];
@@ -473,26 +887,26 @@ impl Op1Ctx {
             }
             
             Op1Def::Mean() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = 0.0;
                     }
                 }
                 else if opref.bufs.len() == 1 {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(1, jx);
+                        let obufnum = opref.get_type_ref(1, jx)?;
                         let obuf = ctx.op1s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix] += obuf[ix];
@@ -505,26 +919,26 @@ impl Op1Ctx {
             }
             
             Op1Def::Min() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = 0.0;
                     }
                 }
                 else if opref.bufs.len() == 1 {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(1, jx);
+                        let obufnum = opref.get_type_ref(1, jx)?;
                         let obuf = ctx.op1s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix] = buf[ix].min(obuf[ix]);
@@ -534,26 +948,26 @@ impl Op1Ctx {
             }
             
             Op1Def::Max() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = 0.0;
                     }
                 }
                 else if opref.bufs.len() == 1 {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(1, 0);
+                    let obufnum = opref.get_type_ref(1, 0)?;
                     let obuf1 = ctx.op1s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix];
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(1, jx);
+                        let obufnum = opref.get_type_ref(1, jx)?;
                         let obuf = ctx.op1s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix] = buf[ix].max(obuf[ix]);
@@ -566,7 +980,7 @@ impl Op1Ctx {
                 let age = ctx.age() as f32;
                 let min = min.eval(ctx, age);
                 let max = max.eval(ctx, age);
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -574,12 +988,69 @@ impl Op1Ctx {
                 }
             }
 
+            Op1Def::Sub() | Op1Def::Div() | Op1Def::AbsDiff() | Op1Def::Pow() | Op1Def::Mod() | Op1Def::Step() => {
+                // Division by zero and the like are left for sanitize()
+                // to catch.
+                let func: fn(f32, f32) -> f32 = match &opref.op {
+                    Op1Def::Sub() => |val1, val2| val1 - val2,
+                    Op1Def::Div() => |val1, val2| val1 / val2,
+                    Op1Def::AbsDiff() => |val1, val2| (val1 - val2).abs(),
+                    Op1Def::Pow() => |val1, val2| val1.powf(val2),
+                    Op1Def::Mod() => |val1, val2| val1.rem_euclid(val2),
+                    _ => |val1, val2| if val1 < val2 { 0.0 } else { 1.0 },
+                };
+                let obufnum1 = opref.get_type_ref(1, 0)?;
+                let obufnum2 = opref.get_type_ref(1, 1)?;
+                let obuf1 = ctx.op1s[obufnum1].buf.borrow();
+                let obuf2 = ctx.op1s[obufnum2].buf.borrow();
+                assert!(buf.len() == obuf1.len());
+                assert!(buf.len() == obuf2.len());
+                for ix in 0..buf.len() {
+                    buf[ix] = func(obuf1[ix], obuf2[ix]);
+                }
+            }
+
+            Op1Def::SmoothStep() => {
+                let obufnum = opref.get_type_ref(1, 0)?;
+                let edgenum0 = opref.get_type_ref(1, 1)?;
+                let edgenum1 = opref.get_type_ref(1, 2)?;
+                let obuf = ctx.op1s[obufnum].buf.borrow();
+                let edge0 = ctx.op1s[edgenum0].buf.borrow();
+                let edge1 = ctx.op1s[edgenum1].buf.borrow();
+                for ix in 0..buf.len() {
+                    let val = if edge0[ix] == edge1[ix] {
+                        if obuf[ix] < edge0[ix] { 0.0 } else { 1.0 }
+                    }
+                    else {
+                        ((obuf[ix] - edge0[ix]) / (edge1[ix] - edge0[ix])).clamp(0.0, 1.0)
+                    };
+                    buf[ix] = val * val * (3.0 - 2.0 * val);
+                }
+            }
+
+            Op1Def::Remap() => {
+                let obufnum = opref.get_type_ref(1, 0)?;
+                let obuf = ctx.op1s[obufnum].buf.borrow();
+                let mut bounds = Vec::new();
+                for jx in 1..5 {
+                    let bufnum = opref.get_type_ref(1, jx)?;
+                    bounds.push(ctx.op1s[bufnum].buf.borrow());
+                }
+                for ix in 0..buf.len() {
+                    let (inmin, inmax) = (bounds[0][ix], bounds[1][ix]);
+                    let (outmin, outmax) = (bounds[2][ix], bounds[3][ix]);
+                    // Not clamped, so values outside the input range
+                    // carry on past the output range.
+                    buf[ix] = outmin + (obuf[ix] - inmin) * (outmax - outmin) / (inmax - inmin);
+                }
+            }
+
             Op1Def::Shift(offset) => {
                 let age = ctx.age() as f32;
                 let offset = offset.eval(ctx, age);
                 let buflen = buf.len() as i32;
                 let buflen32 = buf.len() as f32;
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -597,7 +1068,7 @@ impl Op1Ctx {
                 let offset = offset.eval(ctx, age);
                 let buflen = buf.len() as i32;
                 let buflen32 = buf.len() as f32;
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 let mut state = ctx.op1s[bufnum].state.borrow_mut();
                 if let Op1State::Decay(historybuf) = &mut *state {
@@ -614,7 +1085,46 @@ impl Op1Ctx {
                     }
                 }
                 else {
-                    panic!("Op1 state mismatch: ShiftDecay");
+                    return Err(opref.err("Op1 state mismatch: ShiftDecay".to_string()));
+                }
+            }
+
+            Op1Def::Blur(kernel, edge, radius) => {
+                let radius = blur_radius(radius, ctx, buf.len()).map_err(|msg| opref.err(msg))?;
+                let mut state = ctx.op1s[bufnum].state.borrow_mut();
+                let weights = match &mut *state {
+                    Op1State::Blur(bstate) => bstate.weights(kernel, radius),
+                    _ => return Err(opref.err("Op1 state mismatch: Blur".to_string())),
+                };
+                let span = (weights.len() / 2) as i32;
+                let buflen = buf.len() as i32;
+                let obufnum = opref.get_type_ref(1, 0)?;
+                let obuf = ctx.op1s[obufnum].buf.borrow();
+                assert!(buf.len() == obuf.len());
+                for ix in 0..buf.len() {
+                    let mut total = 0.0;
+                    for jx in 0..weights.len() {
+                        if let Some(pos) = edge.index(ix as i32 + jx as i32 - span, buflen) {
+                            total += weights[jx] * obuf[pos];
+                        }
+                    }
+                    buf[ix] = total;
+                }
+            }
+
+            Op1Def::Mirror(..) | Op1Def::Reverse() | Op1Def::Tile(_) | Op1Def::Zoom(..) => {
+                let taps = match transform_op1_taps(&opref.op, ctx, buf.len()) {
+                    Some(val) => val,
+                    None => return Err(opref.err("not a transform".to_string())),
+                };
+                let obufnum = opref.get_type_ref(1, 0)?;
+                let obuf = ctx.op1s[obufnum].buf.borrow();
+                assert!(buf.len() == obuf.len());
+                for ix in 0..buf.len() {
+                    let tap = &taps[ix];
+                    let lo = tap.lo.map_or(0.0, |pos| obuf[pos]);
+                    let hi = tap.hi.map_or(0.0, |pos| obuf[pos]);
+                    buf[ix] = lo.lerp(&hi, &tap.frac);
                 }
             }
 
@@ -643,19 +1153,36 @@ impl Op1Ctx {
                     }
                 }
                 else {
-                    panic!("Op1 state mismatch: Noise");
+                    return Err(opref.err("Op1 state mismatch: Noise".to_string()));
                 }
             }
             
             _ => {
-                panic!("unimplemented Op1");
+                return Err(opref.err("unimplemented Op1".to_string()));
             }
         }
+        Ok(())
     }
 }
 
 impl Op3Ctx {
-    pub fn tickop(ctx: &mut ScriptContext, bufnum: usize) {
+    pub fn sanitize(ctx: &ScriptContext, bufnum: usize) {
+        let mut buf = ctx.op3s[bufnum].buf.borrow_mut();
+        let mut count = 0;
+        for ix in 0..buf.len() {
+            let pix = &mut buf[ix];
+            if !(pix.r.is_finite() && pix.g.is_finite() && pix.b.is_finite() && pix.a.is_finite()) {
+                *pix = Pix::new(0.0, 0.0, 0.0);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            let opref = &ctx.script.op3s[bufnum];
+            warn_nonfinite(ctx, &ctx.op3s[bufnum].lastwarn, opref.op.describe(None), &opref.loc, count);
+        }
+    }
+
+    pub fn tickop(ctx: &mut ScriptContext, bufnum: usize) -> Result<(), BeaconError> {
         let opref = &ctx.script.op3s[bufnum];
         //let mut _state = ctx.op3s[bufnum].state.borrow_mut();
         let mut buf = ctx.op3s[bufnum].buf.borrow_mut();
@@ -667,7 +1194,7 @@ impl Op3Ctx {
             }
             
             Op3Def::Invert() => {
-                let obufnum = opref.get_type_ref(3, 0);
+                let obufnum = opref.get_type_ref(3, 0)?;
                 let obuf = ctx.op3s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -676,9 +1203,9 @@ impl Op3Ctx {
             }
 
             Op3Def::RGB() => {
-                let obufnum1 = opref.get_type_ref(1, 0);
-                let obufnum2 = opref.get_type_ref(1, 1);
-                let obufnum3 = opref.get_type_ref(1, 2);
+                let obufnum1 = opref.get_type_ref(1, 0)?;
+                let obufnum2 = opref.get_type_ref(1, 1)?;
+                let obufnum3 = opref.get_type_ref(1, 2)?;
                 let obuf1 = ctx.op1s[obufnum1].buf.borrow();
                 let obuf2 = ctx.op1s[obufnum2].buf.borrow();
                 let obuf3 = ctx.op1s[obufnum3].buf.borrow();
@@ -691,9 +1218,9 @@ impl Op3Ctx {
             }
 
             Op3Def::HSV() => {
-                let obufnum1 = opref.get_type_ref(1, 0);
-                let obufnum2 = opref.get_type_ref(1, 1);
-                let obufnum3 = opref.get_type_ref(1, 2);
+                let obufnum1 = opref.get_type_ref(1, 0)?;
+                let obufnum2 = opref.get_type_ref(1, 1)?;
+                let obufnum3 = opref.get_type_ref(1, 2)?;
                 let obuf1 = ctx.op1s[obufnum1].buf.borrow();
                 let obuf2 = ctx.op1s[obufnum2].buf.borrow();
                 let obuf3 = ctx.op1s[obufnum3].buf.borrow();
@@ -706,7 +1233,7 @@ impl Op3Ctx {
             }
 
             Op3Def::RGBToHSV() => {
-                let obufnum = opref.get_type_ref(3, 0);
+                let obufnum = opref.get_type_ref(3, 0)?;
                 let obuf = ctx.op3s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -716,7 +1243,7 @@ impl Op3Ctx {
             }
 
             Op3Def::HSVToRGB() => {
-                let obufnum = opref.get_type_ref(3, 0);
+                let obufnum = opref.get_type_ref(3, 0)?;
                 let obuf = ctx.op3s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -726,7 +1253,7 @@ impl Op3Ctx {
             }
 
             Op3Def::Grey() => {
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -735,7 +1262,7 @@ impl Op3Ctx {
             }
 
             Op3Def::Gradient(stops) => {
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 let count = stops.len();
@@ -770,7 +1297,7 @@ impl Op3Ctx {
             }
 
             Op3Def::PGradient(stops) => {
-                let obufnum = opref.get_type_ref(1, 0);
+                let obufnum = opref.get_type_ref(1, 0)?;
                 let obuf = ctx.op1s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 let count = stops.len();
@@ -802,143 +1329,149 @@ impl Op3Ctx {
             },
             
             Op3Def::MulS() => {
-                let obufnum1 = opref.get_type_ref(3, 0);
-                let obufnum2 = opref.get_type_ref(1, 1);
+                let obufnum1 = opref.get_type_ref(3, 0)?;
+                let obufnum2 = opref.get_type_ref(1, 1)?;
                 let obuf1 = ctx.op3s[obufnum1].buf.borrow();
                 let obuf2 = ctx.op1s[obufnum2].buf.borrow();
                 assert!(buf.len() == obuf1.len());
                 assert!(buf.len() == obuf2.len());
                 for ix in 0..buf.len() {
-                    buf[ix] = Pix::new(obuf1[ix].r*obuf2[ix], obuf1[ix].g*obuf2[ix], obuf1[ix].b*obuf2[ix]);
+                    // The scalar fades the color out to transparent.
+                    buf[ix] = Pix { r: obuf1[ix].r*obuf2[ix], g: obuf1[ix].g*obuf2[ix], b: obuf1[ix].b*obuf2[ix], a: obuf1[ix].a*obuf2[ix] };
                 }
             }
 
             Op3Def::Sum() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = Pix::new(0.0, 0.0, 0.0);
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(3, jx);
+                        let obufnum = opref.get_type_ref(3, jx)?;
                         let obuf = ctx.op3s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix].r += obuf[ix].r;
                             buf[ix].g += obuf[ix].g;
                             buf[ix].b += obuf[ix].b;
+                            buf[ix].a = (buf[ix].a + obuf[ix].a).min(1.0);
                         }
                     }
                 }
             }
             
             Op3Def::Mean() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = Pix::new(0.0, 0.0, 0.0);
                     }
                 }
                 else if opref.bufs.len() == 1 {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(3, jx);
+                        let obufnum = opref.get_type_ref(3, jx)?;
                         let obuf = ctx.op3s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix].r += obuf[ix].r;
                             buf[ix].g += obuf[ix].g;
                             buf[ix].b += obuf[ix].b;
+                            buf[ix].a += obuf[ix].a;
                         }
                     }
                     for ix in 0..buf.len() {
                         buf[ix].r /= opref.bufs.len() as f32;
                         buf[ix].g /= opref.bufs.len() as f32;
                         buf[ix].b /= opref.bufs.len() as f32;
+                        buf[ix].a /= opref.bufs.len() as f32;
                     }
                 }
             }
             
             Op3Def::Min() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = Pix::new(0.0, 0.0, 0.0);
                     }
                 }
                 else if opref.bufs.len() == 1 {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(3, jx);
+                        let obufnum = opref.get_type_ref(3, jx)?;
                         let obuf = ctx.op3s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix].r = buf[ix].r.min(obuf[ix].r);
                             buf[ix].g = buf[ix].g.min(obuf[ix].g);
                             buf[ix].b = buf[ix].b.min(obuf[ix].b);
+                            buf[ix].a = buf[ix].a.min(obuf[ix].a);
                         }
                     }
                 }
             }
             
             Op3Def::Max() => {
-                if opref.bufs.len() == 0 {
+                if opref.bufs.is_empty() {
                     for ix in 0..buf.len() {
                         buf[ix] = Pix::new(0.0, 0.0, 0.0);
                     }
                 }
                 else if opref.bufs.len() == 1 {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                 }
                 else {
-                    let obufnum = opref.get_type_ref(3, 0);
+                    let obufnum = opref.get_type_ref(3, 0)?;
                     let obuf1 = ctx.op3s[obufnum].buf.borrow();
                     for ix in 0..buf.len() {
                         buf[ix] = obuf1[ix].clone();
                     }
                     for jx in 1..opref.bufs.len() {
-                        let obufnum = opref.get_type_ref(3, jx);
+                        let obufnum = opref.get_type_ref(3, jx)?;
                         let obuf = ctx.op3s[obufnum].buf.borrow();
                         for ix in 0..buf.len() {
                             buf[ix].r = buf[ix].r.max(obuf[ix].r);
                             buf[ix].g = buf[ix].g.max(obuf[ix].g);
                             buf[ix].b = buf[ix].b.max(obuf[ix].b);
+                            buf[ix].a = buf[ix].a.max(obuf[ix].a);
                         }
                     }
                 }
             }
 
             Op3Def::Lerp() => {
-                let obufnum1 = opref.get_type_ref(3, 0);
-                let obufnum2 = opref.get_type_ref(3, 1);
-                let obufnum3 = opref.get_type_ref(1, 2);
+                let obufnum1 = opref.get_type_ref(3, 0)?;
+                let obufnum2 = opref.get_type_ref(3, 1)?;
+                let obufnum3 = opref.get_type_ref(1, 2)?;
                 let obuf1 = ctx.op3s[obufnum1].buf.borrow();
                 let obuf2 = ctx.op3s[obufnum2].buf.borrow();
                 let obuf3 = ctx.op1s[obufnum3].buf.borrow();
@@ -953,9 +1486,9 @@ impl Op3Ctx {
             Op3Def::Mask(threshold) => {
                 let age = ctx.age() as f32;
                 let thresval = threshold.eval(ctx, age);
-                let obufnum1 = opref.get_type_ref(3, 0);
-                let obufnum2 = opref.get_type_ref(3, 1);
-                let obufnum3 = opref.get_type_ref(1, 2);
+                let obufnum1 = opref.get_type_ref(3, 0)?;
+                let obufnum2 = opref.get_type_ref(3, 1)?;
+                let obufnum3 = opref.get_type_ref(1, 2)?;
                 let obuf1 = ctx.op3s[obufnum1].buf.borrow();
                 let obuf2 = ctx.op3s[obufnum2].buf.borrow();
                 let obuf3 = ctx.op1s[obufnum3].buf.borrow();
@@ -972,12 +1505,38 @@ impl Op3Ctx {
                 }
             }
 
+            Op3Def::Blend(mode, opacity) => {
+                let age = ctx.age() as f32;
+                let opacity = opacity.eval(ctx, age);
+                if opref.bufs.is_empty() {
+                    for ix in 0..buf.len() {
+                        buf[ix] = Pix::grey(0.0);
+                    }
+                }
+                else {
+                    let obufnum = opref.get_type_ref(3, 0)?;
+                    let obuf1 = ctx.op3s[obufnum].buf.borrow();
+                    for ix in 0..buf.len() {
+                        buf[ix] = obuf1[ix].clone();
+                    }
+                    // Each layer goes on top of everything before it.
+                    for jx in 1..opref.bufs.len() {
+                        let obufnum = opref.get_type_ref(3, jx)?;
+                        let obuf = ctx.op3s[obufnum].buf.borrow();
+                        for ix in 0..buf.len() {
+                            let res = mode.composite(&buf[ix], &obuf[ix]);
+                            buf[ix] = buf[ix].lerp(&res, opacity);
+                        }
+                    }
+                }
+            }
+
             Op3Def::Shift(offset) => {
                 let age = ctx.age() as f32;
                 let offset = offset.eval(ctx, age);
                 let buflen = buf.len() as i32;
                 let buflen32 = buf.len() as f32;
-                let obufnum = opref.get_type_ref(3, 0);
+                let obufnum = opref.get_type_ref(3, 0)?;
                 let obuf = ctx.op3s[obufnum].buf.borrow();
                 assert!(buf.len() == obuf.len());
                 for ix in 0..buf.len() {
@@ -987,10 +1546,54 @@ impl Op3Ctx {
                     buf[ix].r = obuf[seg.rem_euclid(buflen) as usize].r.lerp(&obuf[(seg+1).rem_euclid(buflen) as usize].r, &frac);
                     buf[ix].g = obuf[seg.rem_euclid(buflen) as usize].g.lerp(&obuf[(seg+1).rem_euclid(buflen) as usize].g, &frac);
                     buf[ix].b = obuf[seg.rem_euclid(buflen) as usize].b.lerp(&obuf[(seg+1).rem_euclid(buflen) as usize].b, &frac);
+                    buf[ix].a = obuf[seg.rem_euclid(buflen) as usize].a.lerp(&obuf[(seg+1).rem_euclid(buflen) as usize].a, &frac);
+                }
+            }
+
+            Op3Def::Blur(kernel, edge, radius) => {
+                let radius = blur_radius(radius, ctx, buf.len()).map_err(|msg| opref.err(msg))?;
+                let mut state = ctx.op3s[bufnum].state.borrow_mut();
+                let weights = match &mut *state {
+                    Op3State::Blur(bstate) => bstate.weights(kernel, radius),
+                    _ => return Err(opref.err("Op3 state mismatch: Blur".to_string())),
+                };
+                let span = (weights.len() / 2) as i32;
+                let buflen = buf.len() as i32;
+                let obufnum = opref.get_type_ref(3, 0)?;
+                let obuf = ctx.op3s[obufnum].buf.borrow();
+                assert!(buf.len() == obuf.len());
+                for ix in 0..buf.len() {
+                    let mut total = Pix::clear();
+                    for jx in 0..weights.len() {
+                        if let Some(pos) = edge.index(ix as i32 + jx as i32 - span, buflen) {
+                            total.r += weights[jx] * obuf[pos].r;
+                            total.g += weights[jx] * obuf[pos].g;
+                            total.b += weights[jx] * obuf[pos].b;
+                            total.a += weights[jx] * obuf[pos].a;
+                        }
+                    }
+                    buf[ix] = total;
+                }
+            }
+
+            Op3Def::Mirror(..) | Op3Def::Reverse() | Op3Def::Tile(_) | Op3Def::Zoom(..) => {
+                let taps = match transform_op3_taps(&opref.op, ctx, buf.len()) {
+                    Some(val) => val,
+                    None => return Err(opref.err("not a transform".to_string())),
+                };
+                let obufnum = opref.get_type_ref(3, 0)?;
+                let obuf = ctx.op3s[obufnum].buf.borrow();
+                assert!(buf.len() == obuf.len());
+                for ix in 0..buf.len() {
+                    let tap = &taps[ix];
+                    let lo = tap.lo.map_or(Pix::clear(), |pos| obuf[pos].clone());
+                    let hi = tap.hi.map_or(Pix::clear(), |pos| obuf[pos].clone());
+                    buf[ix] = lo.lerp(&hi, tap.frac);
                 }
             }
 
             //_ => { panic!("unimplemented Op3"); }
         }
+        Ok(())
     }
 }
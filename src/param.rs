@@ -1,14 +1,14 @@
 use std::fmt;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use rand::Rng;
 
-use crate::waves::WaveShape;
+use crate::waves::{WaveShape, Easing};
 use crate::context::scriptcontext::ScriptContext;
-use crate::runner::RunContext;
 
 // To think about:
 // Params containing params? RandFlat(0, Changing())
-// Params with state? (RandomWalk?) Currently not possible.
-// Params which depend on Ops?
 // (In an ideal universe, Params would be unified with Ops anyway.)
 
 #[derive(Clone)]
@@ -20,14 +20,89 @@ pub enum ParamDef {
     Wave(WaveShape, usize, usize, usize), // shape, min, max, duration
     WaveCycle(WaveShape, usize, usize, usize, usize), // shape, min, max, period, offset
     Sum(Vec<usize>),         // args...
+    Sub(usize, usize),       // arg, arg
+    Mul(usize, usize),       // arg, arg
+    Div(usize, usize),       // arg, arg
+    Mod(usize, usize),       // arg, arg
+    Neg(usize),              // arg
+    Abs(usize),              // arg
+    Min(Vec<usize>),         // args...
+    Max(Vec<usize>),         // args...
+    Clamp(usize, usize, usize), // arg, min, max
+    RandWalk(usize, usize, usize, usize), // start, min, max, step
+    Drift(usize, usize, usize), // start, step, revert
+    SmoothNoise(usize, usize, usize), // min, max, period
+    Sample(SampleMode, BufSlot, usize, usize), // mode, op1 buffer, start, end
+    KeyFrames(Vec<KeyFrame>, LoopMode), // frames (values are args), mode
 
     Quote(usize),   // quotedparam
 }
 
+// How a Sample param reads its buffer. The range is given as fractions
+// of the strip; At uses only the start.
+#[derive(Clone, Copy, Debug)]
+pub enum SampleMode {
+    At,        // the value at a position
+    Mean,      // over the range
+    Max,
+    Min,
+    Brightest, // the position of the largest value in the range
+}
+
+// One point on a KeyFrames timeline. The value is an arg of the param;
+// the easing is the curve from this frame to the next one.
+#[derive(Clone, Debug)]
+pub struct KeyFrame {
+    pub time: f32,
+    pub value: usize,
+    pub ease: Easing,
+}
+
+// What a KeyFrames param does after its last frame.
+#[derive(Clone, Copy, Debug)]
+pub enum LoopMode {
+    Once,     // hold the last value
+    Loop,     // start over
+    PingPong, // run backwards to the start, and so on
+}
+
+// The op1 buffer that a Sample param reads. This is filled in when the
+// script is built, since the buffer doesn't have a number before then.
+pub type BufSlot = Rc<Cell<Option<usize>>>;
+
+impl ParamDef {
+    // Params whose value depends on their own history. Each instance
+    // keeps a ParamState in the ScriptContext.
+    fn has_state(&self) -> bool {
+        matches!(self, ParamDef::RandWalk(..) | ParamDef::Drift(..) | ParamDef::SmoothNoise(..))
+    }
+}
+
+// Keys for stateful param instances. 0 means no state.
+static NEXTKEY: AtomicUsize = AtomicUsize::new(1);
+
+fn newkey(pdef: &ParamDef) -> usize {
+    if pdef.has_state() {
+        NEXTKEY.fetch_add(1, Ordering::Relaxed)
+    }
+    else {
+        0
+    }
+}
+
 #[derive(Clone)]
 pub struct EParam {
     def: ParamDef,
     args: Vec<Param>,
+    key: usize,
+}
+
+// The state of one stateful param instance.
+pub struct ParamState {
+    age: f32,    // when last updated
+    val: f32,
+    origin: f32, // the starting value
+    seed: u32,
 }
 
 #[derive(Clone)]
@@ -48,6 +123,21 @@ impl fmt::Debug for Param {
                 ParamDef::Wave(shape, min, max, duration) => write!(f, "Wave(shape={:?}, min={:?}, max={:?}, duration={:?})", shape, param.args[*min], param.args[*max], param.args[*duration]),
                 ParamDef::WaveCycle(shape, min, max, period, offset) => write!(f, "WaveCycle(shape={:?}, min={:?}, max={:?}, period={:?}, offset={:?})", shape, param.args[*min], param.args[*max], param.args[*period], param.args[*offset]),
                 ParamDef::Sum(args) => write!(f, "Sum({})", args.iter().map(|subp| format!("{:?}", param.args[*subp])).collect::<Vec<_>>().join(", ")),
+                ParamDef::Sub(arg1, arg2) => write!(f, "Sub({:?}, {:?})", param.args[*arg1], param.args[*arg2]),
+                ParamDef::Mul(arg1, arg2) => write!(f, "Mul({:?}, {:?})", param.args[*arg1], param.args[*arg2]),
+                ParamDef::Div(arg1, arg2) => write!(f, "Div({:?}, {:?})", param.args[*arg1], param.args[*arg2]),
+                ParamDef::Mod(arg1, arg2) => write!(f, "Mod({:?}, {:?})", param.args[*arg1], param.args[*arg2]),
+                ParamDef::Neg(arg) => write!(f, "Neg({:?})", param.args[*arg]),
+                ParamDef::Abs(arg) => write!(f, "Abs({:?})", param.args[*arg]),
+                ParamDef::Min(args) => write!(f, "Min({})", args.iter().map(|subp| format!("{:?}", param.args[*subp])).collect::<Vec<_>>().join(", ")),
+                ParamDef::Max(args) => write!(f, "Max({})", args.iter().map(|subp| format!("{:?}", param.args[*subp])).collect::<Vec<_>>().join(", ")),
+                ParamDef::Clamp(arg, min, max) => write!(f, "Clamp({:?}, min={:?}, max={:?})", param.args[*arg], param.args[*min], param.args[*max]),
+                ParamDef::RandWalk(start, min, max, step) => write!(f, "RandWalk(start={:?}, min={:?}, max={:?}, step={:?})", param.args[*start], param.args[*min], param.args[*max], param.args[*step]),
+                ParamDef::Drift(start, step, revert) => write!(f, "Drift(start={:?}, step={:?}, revert={:?})", param.args[*start], param.args[*step], param.args[*revert]),
+                ParamDef::SmoothNoise(min, max, period) => write!(f, "SmoothNoise(min={:?}, max={:?}, period={:?})", param.args[*min], param.args[*max], param.args[*period]),
+                ParamDef::Sample(SampleMode::At, slot, pos, _end) => write!(f, "Sample(At, buf=1/{}, pos={:?})", slotname(slot), param.args[*pos]),
+                ParamDef::Sample(mode, slot, start, end) => write!(f, "Sample({:?}, buf=1/{}, start={:?}, end={:?})", mode, slotname(slot), param.args[*start], param.args[*end]),
+                ParamDef::KeyFrames(frames, mode) => write!(f, "KeyFrames({:?}, {})", mode, frames.iter().map(|frame| format!("{}:{:?}:{:?}", frame.time, param.args[frame.value], frame.ease)).collect::<Vec<_>>().join(", ")),
                 ParamDef::Quote(subp) => write!(f, "Quote({:?})", param.args[*subp])
             },
         }
@@ -57,6 +147,7 @@ impl fmt::Debug for Param {
 impl Param {
     pub fn new(pdef: ParamDef) -> Param {
         let eparam = EParam {
+            key: newkey(&pdef),
             def: pdef,
             args: Vec::default(),
         };
@@ -120,30 +211,170 @@ impl Param {
                     }
                     sum
                 },
-                ParamDef::Quote(_) => {
-                    panic!("eval Quote");
+                ParamDef::Sub(arg1, arg2) => {
+                    param.args[*arg1].eval(ctx, age) - param.args[*arg2].eval(ctx, age)
+                },
+                ParamDef::Mul(arg1, arg2) => {
+                    param.args[*arg1].eval(ctx, age) * param.args[*arg2].eval(ctx, age)
+                },
+                ParamDef::Div(arg1, arg2) => {
+                    param.args[*arg1].eval(ctx, age) / param.args[*arg2].eval(ctx, age)
+                },
+                ParamDef::Mod(arg1, arg2) => {
+                    // Euclidean, so that a position mod 1 stays in 0..1.
+                    param.args[*arg1].eval(ctx, age).rem_euclid(param.args[*arg2].eval(ctx, age))
+                },
+                ParamDef::Neg(arg) => {
+                    -param.args[*arg].eval(ctx, age)
+                },
+                ParamDef::Abs(arg) => {
+                    param.args[*arg].eval(ctx, age).abs()
+                },
+                ParamDef::Min(args) => {
+                    let mut res = f32::INFINITY;
+                    for ix in args {
+                        res = res.min(param.args[*ix].eval(ctx, age));
+                    }
+                    res
+                },
+                ParamDef::Max(args) => {
+                    let mut res = -f32::INFINITY;
+                    for ix in args {
+                        res = res.max(param.args[*ix].eval(ctx, age));
+                    }
+                    res
+                },
+                ParamDef::Clamp(arg, min, max) => {
+                    let val = param.args[*arg].eval(ctx, age);
+                    let min = param.args[*min].eval(ctx, age);
+                    let max = param.args[*max].eval(ctx, age);
+                    val.max(min).min(max)
+                },
+                ParamDef::RandWalk(start, min, max, step) => {
+                    let min = param.args[*min].eval(ctx, age);
+                    let max = param.args[*max].eval(ctx, age);
+                    let step = param.args[*step].eval(ctx, age);
+                    if !ctx.paramstates.borrow().contains_key(&param.key) {
+                        let start = param.args[*start].eval(ctx, age);
+                        let state = newstate(ctx, age, start.max(min).min(max));
+                        ctx.paramstates.borrow_mut().insert(param.key, state);
+                    }
+                    let mut states = ctx.paramstates.borrow_mut();
+                    let state = states.get_mut(&param.key).unwrap();
+                    let dt = age - state.age;
+                    if dt > 0.0 {
+                        let mut val = state.val + gaussian(ctx) * step * dt.sqrt();
+                        // Bounce off the edges.
+                        if val > max {
+                            val = 2.0*max - val;
+                        }
+                        if val < min {
+                            val = 2.0*min - val;
+                        }
+                        state.val = val.max(min).min(max);
+                        state.age = age;
+                    }
+                    state.val
+                },
+                ParamDef::Drift(start, step, revert) => {
+                    let step = param.args[*step].eval(ctx, age);
+                    let revert = param.args[*revert].eval(ctx, age);
+                    if !ctx.paramstates.borrow().contains_key(&param.key) {
+                        let start = param.args[*start].eval(ctx, age);
+                        let state = newstate(ctx, age, start);
+                        ctx.paramstates.borrow_mut().insert(param.key, state);
+                    }
+                    let mut states = ctx.paramstates.borrow_mut();
+                    let state = states.get_mut(&param.key).unwrap();
+                    let dt = age - state.age;
+                    if dt > 0.0 {
+                        // Pulled back toward the start at rate "revert".
+                        let pull = (state.origin - state.val) * (revert * dt).min(1.0);
+                        state.val += pull + gaussian(ctx) * step * dt.sqrt();
+                        state.age = age;
+                    }
+                    state.val
+                },
+                ParamDef::SmoothNoise(min, max, period) => {
+                    let min = param.args[*min].eval(ctx, age);
+                    let max = param.args[*max].eval(ctx, age);
+                    let period = param.args[*period].eval(ctx, age);
+                    let known = ctx.paramstates.borrow().get(&param.key).map(|state| state.seed);
+                    let seed = match known {
+                        Some(seed) => seed,
+                        None => {
+                            let state = newstate(ctx, age, 0.0);
+                            let seed = state.seed;
+                            ctx.paramstates.borrow_mut().insert(param.key, state);
+                            seed
+                        },
+                    };
+                    // Value noise: random values at each multiple of the
+                    // period, smoothly joined.
+                    let pos = age / period;
+                    let ix = pos.floor();
+                    let frac = pos - ix;
+                    let val0 = latticeval(seed, ix as i64);
+                    let val1 = latticeval(seed, ix as i64 + 1);
+                    let smooth = frac * frac * (3.0 - 2.0 * frac);
+                    (val0 + (val1 - val0) * smooth) * (max-min) + min
+                },
+                ParamDef::Sample(mode, slot, start, end) => {
+                    let start = param.args[*start].eval(ctx, age);
+                    let end = param.args[*end].eval(ctx, age);
+                    let bufnum = match slot.get() {
+                        Some(val) => val,
+                        None => return 0.0, // never built; nothing to sample
+                    };
+                    let mut res = 0.0;
+                    ctx.applybuf1(bufnum, |buf| {
+                        res = sample(buf, *mode, start, end);
+                    });
+                    res
+                },
+                ParamDef::KeyFrames(frames, mode) => {
+                    let time = looptime(frames, *mode, age);
+                    let first = &frames[0];
+                    if time <= first.time {
+                        return param.args[first.value].eval(ctx, age);
+                    }
+                    for ix in 1..frames.len() {
+                        let next = &frames[ix];
+                        if time < next.time {
+                            let prev = &frames[ix-1];
+                            let startval = param.args[prev.value].eval(ctx, age);
+                            let endval = param.args[next.value].eval(ctx, age);
+                            let pos = (time - prev.time) / (next.time - prev.time);
+                            return startval + (endval - startval) * prev.ease.ease(pos);
+                        }
+                    }
+                    param.args[frames[frames.len()-1].value].eval(ctx, age)
+                },
+                ParamDef::Quote(subp) => {
+                    // Quotes are resolved by whatever holds them (pulsers).
+                    // Anywhere else, a quote is just the param it quotes.
+                    param.args[*subp].eval(ctx, age)
                 },
             }
         }
     }
 
-    pub fn min(&self, ctx: &ScriptContext, age: f32) -> Option<f32> {
+    pub fn min(&self, age: f32) -> Option<f32> {
         match self {
             Param::Const(val) => Some(*val),
             Param::Param(param) => match &param.def {
                 ParamDef::Constant(val) => Some(*val),
                 ParamDef::RandFlat(min, _max) => {
-                    let min = param.args[*min].min(ctx, age);
-                    min
+                    param.args[*min].min(age)
                 },
                 ParamDef::RandNorm(mean, stdev) => {
-                    let mean = param.args[*mean].min(ctx, age)?;
-                    let stdev = param.args[*stdev].max(ctx, age)?;
+                    let mean = param.args[*mean].min(age)?;
+                    let stdev = param.args[*stdev].max(age)?;
                     Some((-1.5 * stdev / 0.522) + mean)
                 },
                 ParamDef::Changing(start, velocity) => {
-                    let start = param.args[*start].min(ctx, age)?;
-                    let velocity = param.args[*velocity].min(ctx, age)?;
+                    let start = param.args[*start].min(age)?;
+                    let velocity = param.args[*velocity].min(age)?;
                     if velocity < 0.0 {
                         None
                     }
@@ -152,44 +383,125 @@ impl Param {
                     }
                 },
                 ParamDef::Wave(_shape, min, _max, _period) => {
-                    let min = param.args[*min].min(ctx, age);
-                    min
+                    param.args[*min].min(age)
                 },
                 ParamDef::WaveCycle(_shape, min, _max, _period, _offset) => {
-                    let min = param.args[*min].min(ctx, age);
-                    min
+                    param.args[*min].min(age)
                 }
                 ParamDef::Sum(args) => {
                     let mut sum = 0.0;
                     for ix in args {
-                        sum += param.args[*ix].min(ctx, age)?;
+                        sum += param.args[*ix].min(age)?;
                     }
                     Some(sum)
                 },
-                ParamDef::Quote(_) => {
-                    panic!("eval Quote");
+                ParamDef::Sub(arg1, arg2) => {
+                    let val1 = param.args[*arg1].min(age)?;
+                    let val2 = param.args[*arg2].max(age)?;
+                    Some(val1 - val2)
+                },
+                ParamDef::Mul(arg1, arg2) => {
+                    let (lo, _hi) = mulbounds(&param.args[*arg1], &param.args[*arg2], age)?;
+                    Some(lo)
+                },
+                ParamDef::Div(arg1, arg2) => {
+                    let (lo, _hi) = divbounds(&param.args[*arg1], &param.args[*arg2], age)?;
+                    Some(lo)
+                },
+                ParamDef::Mod(_arg1, arg2) => {
+                    let min2 = param.args[*arg2].min(age)?;
+                    if min2 > 0.0 {
+                        Some(0.0)
+                    }
+                    else {
+                        None
+                    }
+                },
+                ParamDef::Neg(arg) => {
+                    let max = param.args[*arg].max(age)?;
+                    Some(-max)
+                },
+                ParamDef::Abs(arg) => {
+                    let min = param.args[*arg].min(age);
+                    let max = param.args[*arg].max(age);
+                    match (min, max) {
+                        (Some(min), _) if min >= 0.0 => Some(min),
+                        (_, Some(max)) if max <= 0.0 => Some(-max),
+                        _ => Some(0.0),
+                    }
+                },
+                ParamDef::Min(args) => {
+                    let mut res = f32::INFINITY;
+                    for ix in args {
+                        res = res.min(param.args[*ix].min(age)?);
+                    }
+                    Some(res)
+                },
+                ParamDef::Max(args) => {
+                    // The largest of the lower bounds; unbounded args don't matter.
+                    let mut res: Option<f32> = None;
+                    for ix in args {
+                        if let Some(val) = param.args[*ix].min(age) {
+                            res = Some(res.map_or(val, |res| res.max(val)));
+                        }
+                    }
+                    res
+                },
+                ParamDef::Clamp(arg, min, _max) => {
+                    let min = param.args[*min].min(age)?;
+                    match param.args[*arg].min(age) {
+                        Some(val) => Some(val.max(min)),
+                        None => Some(min),
+                    }
+                },
+                ParamDef::RandWalk(_start, min, _max, _step) => {
+                    param.args[*min].min(age)
+                },
+                ParamDef::Drift(_start, _step, _revert) => {
+                    None
+                },
+                ParamDef::SmoothNoise(min, _max, _period) => {
+                    param.args[*min].min(age)
+                },
+                ParamDef::Sample(SampleMode::Brightest, _slot, start, end) => {
+                    let start = param.args[*start].min(age)?;
+                    let end = param.args[*end].min(age)?;
+                    Some(start.min(end))
+                },
+                ParamDef::Sample(_mode, _slot, _start, _end) => {
+                    None
+                },
+                ParamDef::KeyFrames(frames, _mode) => {
+                    // Every easing stays between the two values it connects.
+                    let mut res = f32::INFINITY;
+                    for frame in frames {
+                        res = res.min(param.args[frame.value].min(age)?);
+                    }
+                    Some(res)
+                },
+                ParamDef::Quote(subp) => {
+                    param.args[*subp].min(age)
                 },
             },
         }
     }
 
-    pub fn max(&self, ctx: &ScriptContext, age: f32) -> Option<f32> {
+    pub fn max(&self, age: f32) -> Option<f32> {
         match self {
             Param::Const(val) => Some(*val),
             Param::Param(param) => match &param.def {
                 ParamDef::Constant(val) => Some(*val),
                 ParamDef::RandFlat(_min, max) => {
-                    let max = param.args[*max].max(ctx, age);
-                    max
+                    param.args[*max].max(age)
                 },
                 ParamDef::RandNorm(mean, stdev) => {
-                    let mean = param.args[*mean].max(ctx, age)?;
-                    let stdev = param.args[*stdev].max(ctx, age)?;
+                    let mean = param.args[*mean].max(age)?;
+                    let stdev = param.args[*stdev].max(age)?;
                     Some((1.5 * stdev / 0.522) + mean)
                 },
                 ParamDef::Changing(start, velocity) => {
-                    let start = param.args[*start].max(ctx, age)?;
-                    let velocity = param.args[*velocity].max(ctx, age)?;
+                    let start = param.args[*start].max(age)?;
+                    let velocity = param.args[*velocity].max(age)?;
                     if velocity > 0.0 {
                         None
                     }
@@ -198,22 +510,100 @@ impl Param {
                     }
                 },
                 ParamDef::Wave(_shape, _min, max, _period) => {
-                    let max = param.args[*max].max(ctx, age);
-                    max
+                    param.args[*max].max(age)
                 },
                 ParamDef::WaveCycle(_shape, _min, max, _period, _offset) => {
-                    let max = param.args[*max].max(ctx, age);
-                    max
+                    param.args[*max].max(age)
                 },
                 ParamDef::Sum(args) => {
                     let mut sum = 0.0;
                     for ix in args {
-                        sum += param.args[*ix].max(ctx, age)?;
+                        sum += param.args[*ix].max(age)?;
                     }
                     Some(sum)
                 },
-                ParamDef::Quote(_) => {
-                    panic!("eval Quote");
+                ParamDef::Sub(arg1, arg2) => {
+                    let val1 = param.args[*arg1].max(age)?;
+                    let val2 = param.args[*arg2].min(age)?;
+                    Some(val1 - val2)
+                },
+                ParamDef::Mul(arg1, arg2) => {
+                    let (_lo, hi) = mulbounds(&param.args[*arg1], &param.args[*arg2], age)?;
+                    Some(hi)
+                },
+                ParamDef::Div(arg1, arg2) => {
+                    let (_lo, hi) = divbounds(&param.args[*arg1], &param.args[*arg2], age)?;
+                    Some(hi)
+                },
+                ParamDef::Mod(_arg1, arg2) => {
+                    let min2 = param.args[*arg2].min(age)?;
+                    let max2 = param.args[*arg2].max(age)?;
+                    if min2 > 0.0 {
+                        Some(max2)
+                    }
+                    else {
+                        None
+                    }
+                },
+                ParamDef::Neg(arg) => {
+                    let min = param.args[*arg].min(age)?;
+                    Some(-min)
+                },
+                ParamDef::Abs(arg) => {
+                    let min = param.args[*arg].min(age)?;
+                    let max = param.args[*arg].max(age)?;
+                    Some(min.abs().max(max.abs()))
+                },
+                ParamDef::Min(args) => {
+                    // The smallest of the upper bounds; unbounded args don't matter.
+                    let mut res: Option<f32> = None;
+                    for ix in args {
+                        if let Some(val) = param.args[*ix].max(age) {
+                            res = Some(res.map_or(val, |res| res.min(val)));
+                        }
+                    }
+                    res
+                },
+                ParamDef::Max(args) => {
+                    let mut res = -f32::INFINITY;
+                    for ix in args {
+                        res = res.max(param.args[*ix].max(age)?);
+                    }
+                    Some(res)
+                },
+                ParamDef::Clamp(arg, _min, max) => {
+                    let max = param.args[*max].max(age)?;
+                    match param.args[*arg].max(age) {
+                        Some(val) => Some(val.min(max)),
+                        None => Some(max),
+                    }
+                },
+                ParamDef::RandWalk(_start, _min, max, _step) => {
+                    param.args[*max].max(age)
+                },
+                ParamDef::Drift(_start, _step, _revert) => {
+                    None
+                },
+                ParamDef::SmoothNoise(_min, max, _period) => {
+                    param.args[*max].max(age)
+                },
+                ParamDef::Sample(SampleMode::Brightest, _slot, start, end) => {
+                    let start = param.args[*start].max(age)?;
+                    let end = param.args[*end].max(age)?;
+                    Some(start.max(end))
+                },
+                ParamDef::Sample(_mode, _slot, _start, _end) => {
+                    None
+                },
+                ParamDef::KeyFrames(frames, _mode) => {
+                    let mut res = f32::NEG_INFINITY;
+                    for frame in frames {
+                        res = res.max(param.args[frame.value].max(age)?);
+                    }
+                    Some(res)
+                },
+                ParamDef::Quote(subp) => {
+                    param.args[*subp].max(age)
                 },
             },
         }
@@ -227,9 +617,13 @@ impl Param {
                     match &param.args[*subp] {
                         Param::Const(val) => Param::newconst(*val),
                         Param::Param(subp) => {
+                            // A stateful param gets a fresh instance, so
+                            // that (for example) every pulse walks
+                            // separately.
                             let newp = EParam {
                                 def: subp.def.clone(),
                                 args: subp.args.iter().map(|arg| arg.resolve(ctx, age)).collect(),
+                                key: newkey(&subp.def),
                             };
                             Param::Param(Box::new(newp))
                         },
@@ -240,4 +634,129 @@ impl Param {
         }
     }
 
+    // Discard the state of a param which won't be used again.
+    pub fn release(&self, ctx: &ScriptContext) {
+        if let Param::Param(param) = self {
+            if param.key != 0 {
+                ctx.paramstates.borrow_mut().remove(&param.key);
+            }
+            for arg in &param.args {
+                arg.release(ctx);
+            }
+        }
+    }
+
+}
+
+// Where we are on a KeyFrames timeline, which runs from zero to the
+// last frame's time. (The frames are sorted.)
+fn looptime(frames: &[KeyFrame], mode: LoopMode, age: f32) -> f32 {
+    let total = frames[frames.len()-1].time;
+    if total <= 0.0 {
+        return age;
+    }
+    match mode {
+        LoopMode::Once => age,
+        LoopMode::Loop => age.rem_euclid(total),
+        LoopMode::PingPong => {
+            let time = age.rem_euclid(2.0 * total);
+            if time > total {
+                2.0 * total - time
+            }
+            else {
+                time
+            }
+        },
+    }
+}
+
+fn slotname(slot: &BufSlot) -> String {
+    match slot.get() {
+        Some(bufnum) => bufnum.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+// Read a buffer. Positions are fractions of the buffer, so they work at
+// any strip size.
+fn sample(buf: &[f32], mode: SampleMode, start: f32, end: f32) -> f32 {
+    if buf.is_empty() {
+        return 0.0;
+    }
+    let last = buf.len() - 1;
+    let index = |pos: f32| -> usize {
+        ((pos * buf.len() as f32) as isize).clamp(0, last as isize) as usize
+    };
+    let (lo, hi) = if start <= end { (start, end) } else { (end, start) };
+    let range = &buf[index(lo) ..= index(hi)];
+    match mode {
+        SampleMode::At => buf[index(start)],
+        SampleMode::Mean => range.iter().sum::<f32>() / range.len() as f32,
+        SampleMode::Max => range.iter().fold(-f32::INFINITY, |acc, val| acc.max(*val)),
+        SampleMode::Min => range.iter().fold(f32::INFINITY, |acc, val| acc.min(*val)),
+        SampleMode::Brightest => {
+            let mut best = 0;
+            for ix in 1..range.len() {
+                if range[ix] > range[best] {
+                    best = ix;
+                }
+            }
+            // The middle of the brightest pixel.
+            (index(lo) + best) as f32 / buf.len() as f32 + 0.5 / buf.len() as f32
+        },
+    }
+}
+
+fn newstate(ctx: &ScriptContext, age: f32, val: f32) -> ParamState {
+    ParamState {
+        age: age,
+        val: val,
+        origin: val,
+        seed: ctx.rng.borrow_mut().gen(),
+    }
+}
+
+// Roughly normal, mean 0 and deviation 1. (The same approximation as
+// RandNorm.)
+fn gaussian(ctx: &ScriptContext) -> f32 {
+    let mut rng = ctx.rng.borrow_mut();
+    let val = rng.gen_range(0.0..1.0) + rng.gen_range(0.0..1.0) + rng.gen_range(0.0..1.0) - 1.5;
+    val / 0.522
+}
+
+// A repeatable random value in 0..1 for each integer.
+fn latticeval(seed: u32, ix: i64) -> f32 {
+    let mut val = (ix as u64).wrapping_mul(0x9E3779B97F4A7C15) ^ (seed as u64);
+    val ^= val >> 33;
+    val = val.wrapping_mul(0xFF51AFD7ED558CCD);
+    val ^= val >> 33;
+    ((val >> 40) as f32) / ((1u64 << 24) as f32)
+}
+
+// Interval arithmetic for the product of two params. Returns None if
+// either side is unbounded.
+fn mulbounds(arg1: &Param, arg2: &Param, age: f32) -> Option<(f32, f32)> {
+    let min1 = arg1.min(age)?;
+    let max1 = arg1.max(age)?;
+    let min2 = arg2.min(age)?;
+    let max2 = arg2.max(age)?;
+    let vals = [min1*min2, min1*max2, max1*min2, max1*max2];
+    let lo = vals.iter().fold(f32::INFINITY, |acc, val| acc.min(*val));
+    let hi = vals.iter().fold(-f32::INFINITY, |acc, val| acc.max(*val));
+    Some((lo, hi))
+}
+
+// Same for a quotient. If the divisor can reach zero, there's no bound.
+fn divbounds(arg1: &Param, arg2: &Param, age: f32) -> Option<(f32, f32)> {
+    let min1 = arg1.min(age)?;
+    let max1 = arg1.max(age)?;
+    let min2 = arg2.min(age)?;
+    let max2 = arg2.max(age)?;
+    if min2 <= 0.0 && max2 >= 0.0 {
+        return None;
+    }
+    let vals = [min1/min2, min1/max2, max1/min2, max1/max2];
+    let lo = vals.iter().fold(f32::INFINITY, |acc, val| acc.min(*val));
+    let hi = vals.iter().fold(-f32::INFINITY, |acc, val| acc.max(*val));
+    Some((lo, hi))
 }
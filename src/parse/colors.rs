@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+
+use crate::pixel::Pix;
+use crate::parse::tree::{ParseItems, ParseNode, ParseTerm, split_unit};
+use crate::parse::diag::{Diagnostic, ParseError};
+use crate::parse::layout::{get_param_layout, get_op1_layout, get_op3_layout, get_waveshape, get_easing, get_loopmode, get_blurkernel, get_edgemode, get_blendmode, get_envelope_layout};
+
+// Remove all "color" items from the list, and then turn every color word
+// into a color term. Color words are:
+//    red, teal, amber...        (named colors, or the script's own)
+//    hsv(0.1, 1, 1)             (hue, saturation, value)
+//    hsl(0.1, 1, 0.5)           (hue, saturation, lightness)
+//    2700K                      (color temperature)
+// A script defines its own named colors like this:
+//    color sky: hsv(0.55, 0.6, 1)
+// These may override the standard names.
+pub fn resolve_colors(itemls: &mut ParseItems) -> Result<(), ParseError> {
+    let mut consts: HashMap<String, Pix<f32>> = HashMap::new();
+    let mut diags: Vec<Diagnostic> = Vec::default();
+
+    let mut remaining: Vec<ParseNode> = Vec::default();
+    for item in itemls.items.drain(..) {
+        if let ParseTerm::ColorDef(name) = &item.term {
+            if consts.contains_key(name) {
+                diags.push(Diagnostic::new(&item.loc, format!("color constant has two definitions: {}", name)));
+                continue;
+            }
+            if isbuiltin(name) {
+                diags.push(Diagnostic::new(&item.loc, format!("color constant name is already an op or param: {}", name)));
+                continue;
+            }
+            // A constant may be defined in terms of earlier ones.
+            match item.params.items.first().and_then(|body| color_word(body, &consts)) {
+                Some(pix) => {
+                    consts.insert(name.to_string(), pix);
+                },
+                None => {
+                    diags.push(Diagnostic::new(&item.loc, format!("color constant must be a color: {}", name)));
+                },
+            }
+        }
+        else {
+            remaining.push(item);
+        }
+    }
+
+    for item in remaining.iter_mut() {
+        resolve_node(item, &consts);
+    }
+    itemls.items = remaining;
+
+    if !diags.is_empty() {
+        return Err(ParseError { diags: diags });
+    }
+    return Ok(());
+}
+
+fn isbuiltin(name: &str) -> bool {
+    return get_param_layout(name).is_some()
+        || get_op1_layout(name).is_some()
+        || get_op3_layout(name).is_some()
+        || get_waveshape(name).is_some()
+        || get_easing(name).is_some()
+        || get_loopmode(name).is_some()
+        || get_blurkernel(name).is_some()
+        || get_edgemode(name).is_some()
+        || get_blendmode(name).is_some()
+        || get_envelope_layout(name).is_some();
+}
+
+fn resolve_node(nod: &mut ParseNode, consts: &HashMap<String, Pix<f32>>) {
+    if let Some(pix) = color_word(nod, consts) {
+        nod.term = ParseTerm::Color(pix);
+        nod.params.items.clear();
+        return;
+    }
+
+    for item in nod.params.items.iter_mut() {
+        resolve_node(item, consts);
+    }
+}
+
+// The color that a node stands for, if it's a color word.
+fn color_word(nod: &ParseNode, consts: &HashMap<String, Pix<f32>>) -> Option<Pix<f32>> {
+    let name = match &nod.term {
+        ParseTerm::Color(pix) => return Some(pix.clone()),
+        ParseTerm::Ident(val) => val.as_str(),
+        _ => return None,
+    };
+
+    if nod.params.items.is_empty() {
+        if let Some(pix) = consts.get(name) {
+            return Some(pix.clone());
+        }
+        if let Some(pix) = NAMEDCOLORS.get(name) {
+            return Some(pix.clone());
+        }
+        if let Some((val, "K")) = split_unit(name) {
+            return Some(Pix::from_kelvin(val));
+        }
+        return None;
+    }
+
+    // "hsv(h, s, v)" with three plain numbers is a constant color. (With
+    // anything else, it's the hsv op.)
+    if name == "hsv" || name == "hsl" {
+        let mut vals: Vec<f32> = Vec::default();
+        for item in &nod.params.items {
+            match (&item.key, &item.term) {
+                (None, ParseTerm::Number(val)) => vals.push(*val),
+                _ => return None,
+            }
+        }
+        if vals.len() != 3 {
+            return None;
+        }
+        if name == "hsv" {
+            return Some(Pix::from_hsv(vals[0], vals[1], vals[2]));
+        }
+        return Some(Pix::from_hsl(vals[0], vals[1], vals[2]));
+    }
+
+    None
+}
+
+fn rgb(val: u32) -> Pix<f32> {
+    Pix::new(((val >> 16) & 0xFF) as f32 / 255.0, ((val >> 8) & 0xFF) as f32 / 255.0, (val & 0xFF) as f32 / 255.0)
+}
+
+lazy_static! {
+    // Mostly the CSS names. "grey" is left out because that's an op.
+    static ref NAMEDCOLORS: HashMap<&'static str, Pix<f32>> = {
+        let mut map = HashMap::new();
+        map.insert("black", rgb(0x000000));
+        map.insert("white", rgb(0xFFFFFF));
+        map.insert("silver", rgb(0xC0C0C0));
+        map.insert("gray", rgb(0x808080));
+        map.insert("red", rgb(0xFF0000));
+        map.insert("crimson", rgb(0xDC143C));
+        map.insert("maroon", rgb(0x800000));
+        map.insert("pink", rgb(0xFFC0CB));
+        map.insert("hotpink", rgb(0xFF69B4));
+        map.insert("coral", rgb(0xFF7F50));
+        map.insert("salmon", rgb(0xFA8072));
+        map.insert("orange", rgb(0xFFA500));
+        map.insert("amber", rgb(0xFFBF00));
+        map.insert("gold", rgb(0xFFD700));
+        map.insert("yellow", rgb(0xFFFF00));
+        map.insert("khaki", rgb(0xF0E68C));
+        map.insert("lime", rgb(0x00FF00));
+        map.insert("green", rgb(0x008000));
+        map.insert("olive", rgb(0x808000));
+        map.insert("chartreuse", rgb(0x7FFF00));
+        map.insert("mint", rgb(0x98FF98));
+        map.insert("teal", rgb(0x008080));
+        map.insert("cyan", rgb(0x00FFFF));
+        map.insert("aqua", rgb(0x00FFFF));
+        map.insert("turquoise", rgb(0x40E0D0));
+        map.insert("skyblue", rgb(0x87CEEB));
+        map.insert("azure", rgb(0x007FFF));
+        map.insert("blue", rgb(0x0000FF));
+        map.insert("navy", rgb(0x000080));
+        map.insert("indigo", rgb(0x4B0082));
+        map.insert("violet", rgb(0xEE82EE));
+        map.insert("purple", rgb(0x800080));
+        map.insert("magenta", rgb(0xFF00FF));
+        map.insert("fuchsia", rgb(0xFF00FF));
+        map.insert("lavender", rgb(0xE6E6FA));
+        map.insert("brown", rgb(0xA52A2A));
+        map.insert("chocolate", rgb(0xD2691E));
+        map.insert("tan", rgb(0xD2B48C));
+        map.insert("beige", rgb(0xF5F5DC));
+        map
+    };
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::rc::Rc;
+
+// A position in a script file: the line, and the span of columns
+// (zero-based, in bytes) within it.
+#[derive(Clone, Debug)]
+pub struct SourceLoc {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub column: usize,
+    pub span: usize,
+    pub text: Rc<str>, // the whole source line
+}
+
+impl SourceLoc {
+    pub fn new(file: &Rc<str>, line: usize, column: usize, span: usize, text: &Rc<str>) -> SourceLoc {
+        SourceLoc {
+            file: file.clone(),
+            line: line,
+            column: column,
+            span: span,
+            text: text.clone(),
+        }
+    }
+
+    // A location within this one, given as an offset and length
+    // relative to its start.
+    pub fn sub(&self, offset: usize, span: usize) -> SourceLoc {
+        SourceLoc {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column + offset,
+            span: span,
+            text: self.text.clone(),
+        }
+    }
+}
+
+// What sort of mistake a diagnostic reports.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DiagKind {
+    Parse, // the script can't be read or built
+    Type,  // an op is given the wrong type of input
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub loc: Option<SourceLoc>,
+    pub message: String,
+    pub kind: DiagKind,
+}
+
+impl Diagnostic {
+    pub fn new(loc: &SourceLoc, message: String) -> Diagnostic {
+        Diagnostic {
+            loc: Some(loc.clone()),
+            message: message,
+            kind: DiagKind::Parse,
+        }
+    }
+
+    pub fn bare(message: String) -> Diagnostic {
+        Diagnostic {
+            loc: None,
+            message: message,
+            kind: DiagKind::Parse,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: DiagKind) -> Diagnostic {
+        self.kind = kind;
+        self
+    }
+}
+
+// Rendered like:
+//
+//    error: too many params
+//      --> scripts/foo.pab:12:15
+//       |
+//    12 |   muls: $F00, 3, 4
+//       |               ^
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.message)?;
+        if let Some(loc) = &self.loc {
+            let linestr = loc.line.to_string();
+            let gutter = " ".repeat(linestr.len());
+            write!(f, "\n{}--> {}:{}:{}", gutter, loc.file, loc.line, loc.column+1)?;
+            write!(f, "\n{} |", gutter)?;
+            write!(f, "\n{} | {}", linestr, loc.text)?;
+            let lead = loc.text.get(..loc.column).unwrap_or("").chars().count();
+            let width = loc.text.get(loc.column..loc.column+loc.span).map(|val| val.chars().count()).unwrap_or(1);
+            write!(f, "\n{} | {}{}", gutter, " ".repeat(lead), "^".repeat(width.max(1)))?;
+        }
+        Ok(())
+    }
+}
+
+// All the diagnostics found while parsing a script.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub diags: Vec<Diagnostic>,
+}
+
+impl From<Diagnostic> for ParseError {
+    fn from(diag: Diagnostic) -> ParseError {
+        ParseError { diags: vec![diag] }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (ix, diag) in self.diags.iter().enumerate() {
+            if ix > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diag)?;
+        }
+        if self.diags.len() > 1 {
+            write!(f, "\n\n{} errors", self.diags.len())?;
+        }
+        Ok(())
+    }
+}
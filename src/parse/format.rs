@@ -0,0 +1,458 @@
+use std::fs;
+use std::path::Path;
+
+use crate::pixel::Pix;
+use crate::error::BeaconError;
+use crate::parse::{parse_script, parse_script_str};
+use crate::parse::source::FileSource;
+use crate::parse::units::StripUnits;
+use crate::parse::tree::{ParseTerm, ParseNode, ParseItems, parse_tree_raw};
+use crate::parse::layout::{OpLayoutParam, OpLayoutType};
+use crate::parse::layout::{get_param_layout, get_gradstop_layout, get_keyframe_layout, get_envelope_layout, get_op1_layout, get_op3_layout};
+use crate::parse::match_children;
+
+// Lines longer than this are broken into indented blocks.
+const LINEWIDTH: usize = 80;
+
+// What a node is expected to be, which decides how its children are
+// laid out.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Op,
+    Param,
+    GradStop,
+    KeyFrame,
+    PulseShape,
+    Leaf,
+    Unknown, // macro calls and the like; keep as written
+}
+
+struct Child<'a> {
+    label: Option<String>,
+    nod: &'a ParseNode,
+    kind: Kind,
+}
+
+// Reformat a script file in place. The script must parse before and
+// after, to the same ops, or the file is left alone. Returns whether
+// the file changed.
+pub fn format_file(filename: &str, units: &StripUnits) -> Result<bool, BeaconError> {
+    let script = parse_script(filename, units)?;
+    let itemls = parse_tree_raw(&FileSource, filename)?;
+    let text = format_items(&itemls);
+
+    let orig = fs::read_to_string(filename)
+        .map_err(|err| BeaconError::output(format!("{}: {}", filename, err)))?;
+    if orig == text {
+        return Ok(false);
+    }
+
+    // Check the new text under the old name, so that includes resolve
+    // the same way.
+    let same = match parse_script_str(&text, filename, units) {
+        Ok(newscript) => newscript.describe_tree() == script.describe_tree(),
+        Err(_) => false,
+    };
+    if !same {
+        return Err(BeaconError::output(format!("{}: formatted script does not match the original; not rewritten", filename)));
+    }
+
+    // Write alongside the original and rename, so that a failed write
+    // doesn't lose it.
+    let path = Path::new(filename);
+    let tempname = match path.file_name() {
+        Some(name) => path.with_file_name(format!(".{}.fmt", name.to_string_lossy())),
+        None => return Err(BeaconError::output(format!("{}: not a file", filename))),
+    };
+    let tempstr = tempname.to_string_lossy().to_string();
+    fs::write(&tempname, &text)
+        .map_err(|err| BeaconError::output(format!("{}: {}", tempstr, err)))?;
+
+    fs::rename(&tempname, filename)
+        .map_err(|err| BeaconError::output(format!("{}: {}", filename, err)))?;
+    Ok(true)
+}
+
+// Turn a tree back into script text.
+pub fn format_items(itemls: &ParseItems) -> String {
+    let mut out = String::new();
+    for item in &itemls.items {
+        let label = item.key.clone();
+        let kind = match &item.term {
+            ParseTerm::Define(_, _) | ParseTerm::ColorDef(_) => Kind::Unknown,
+            _ => Kind::Op,
+        };
+        format_node(&mut out, &Child { label: label, nod: item, kind: kind }, 0, true);
+    }
+    for line in &itemls.trailing {
+        push_line(&mut out, 0, line);
+    }
+    out
+}
+
+fn push_line(out: &mut String, indent: usize, line: &str) {
+    if !line.is_empty() {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(line);
+    }
+    out.push('\n');
+}
+
+fn format_node(out: &mut String, child: &Child, indent: usize, first: bool) {
+    let nod = child.nod;
+    for (ix, line) in nod.leading.iter().enumerate() {
+        // No blank line at the top of a block.
+        if first && ix == 0 && line.is_empty() && indent > 0 {
+            continue;
+        }
+        push_line(out, indent, line);
+    }
+
+    if let ParseTerm::Define(_, _) = &nod.term {
+        push_line(out, indent, &format!("{}:", term_text(&nod.term)));
+        for (ix, item) in nod.params.items.iter().enumerate() {
+            let sub = Child { label: item.key.clone(), nod: item, kind: Kind::Op };
+            format_node(out, &sub, indent+2, ix == 0);
+        }
+        return;
+    }
+
+    let head = head_text(child);
+    let children = children_of(child);
+    if children.is_empty() {
+        push_line(out, indent, &head);
+        return;
+    }
+
+    if let Some(text) = inline_text(&children) {
+        let line = format!("{}: {}", head, text);
+        if indent + line.len() <= LINEWIDTH {
+            push_line(out, indent, &line);
+            return;
+        }
+    }
+
+    push_line(out, indent, &head);
+    for (ix, sub) in children.iter().enumerate() {
+        format_node(out, sub, indent+2, ix == 0);
+    }
+}
+
+// The node's own text, with its label: "pos=quote".
+fn head_text(child: &Child) -> String {
+    if let Some(text) = color_call_text(child.nod) {
+        return match &child.label {
+            Some(label) => format!("{}={}", label, text),
+            None => text,
+        };
+    }
+    let term = match child.kind {
+        Kind::Param => {
+            expr_text(child.nod, 0).unwrap_or_else(|| term_text(&child.nod.term))
+        },
+        _ => term_text(&child.nod.term),
+    };
+    match &child.label {
+        Some(label) => format!("{}={}", label, term),
+        None => term,
+    }
+}
+
+fn term_text(term: &ParseTerm) -> String {
+    match term {
+        ParseTerm::Number(val) => format!("{}", val),
+        ParseTerm::Color(pix) => color_text(pix),
+        ParseTerm::Ident(val) => val.to_string(),
+        ParseTerm::VarName(val) => format!("'{}", val),
+        ParseTerm::Define(name, args) => {
+            if args.is_empty() {
+                format!("define {}", name)
+            }
+            else {
+                format!("define {}({})", name, args.join(", "))
+            }
+        },
+        ParseTerm::ColorDef(name) => format!("color {}", name),
+    }
+}
+
+// A constant color written as a call: "hsv(0.55, 0.6, 1)".
+fn color_call_text(nod: &ParseNode) -> Option<String> {
+    let name = match &nod.term {
+        ParseTerm::Ident(val) if val == "hsv" || val == "hsl" => val,
+        _ => return None,
+    };
+    let mut parts: Vec<String> = Vec::default();
+    for item in &nod.params.items {
+        match &item.term {
+            ParseTerm::Number(val) if item.key.is_none() && item.leading.is_empty() => {
+                parts.push(format!("{}", val));
+            },
+            _ => return None,
+        }
+    }
+    if parts.len() != 3 {
+        return None;
+    }
+    Some(format!("{}({})", name, parts.join(", ")))
+}
+
+// "$F0C" if that's exact, otherwise "$FF00CC".
+fn color_text(pix: &Pix<f32>) -> String {
+    // Translucent colors get a fourth digit (or pair) for alpha. A fully
+    // transparent color comes out black, since that's all it is.
+    let (rval, gval, bval) = pix.unpremultiplied();
+    let mut chans = vec![rval, gval, bval];
+    if pix.a < 1.0 {
+        chans.push(pix.a);
+    }
+    let vals: Vec<u32> = chans.iter()
+        .map(|val| (val.clamp(0.0, 1.0) * 255.0).round() as u32)
+        .collect();
+    if vals.iter().all(|val| val % 17 == 0) {
+        return format!("${}", vals.iter().map(|val| format!("{:X}", val/17)).collect::<String>());
+    }
+    format!("${}", vals.iter().map(|val| format!("{:02X}", val)).collect::<String>())
+}
+
+// The children of a node, one line's worth: "a, b, c". Only the last
+// child may have children of its own. Returns None if the children need
+// a block.
+fn inline_text(children: &Vec<Child>) -> Option<String> {
+    let mut parts: Vec<String> = Vec::default();
+    for (ix, child) in children.iter().enumerate() {
+        if !child.nod.leading.is_empty() {
+            return None;
+        }
+        if let ParseTerm::Define(_, _) = &child.nod.term {
+            return None;
+        }
+        let head = head_text(child);
+        let grandchildren = children_of(child);
+        if grandchildren.is_empty() {
+            parts.push(head);
+        }
+        else if ix+1 == children.len() {
+            parts.push(format!("{}: {}", head, inline_text(&grandchildren)?));
+        }
+        else {
+            return None;
+        }
+    }
+    Some(parts.join(", "))
+}
+
+// The children to print under a node. An infix expression has already
+// covered its children.
+fn children_of<'a>(child: &Child<'a>) -> Vec<Child<'a>> {
+    if child.kind == Kind::Param && expr_text(child.nod, 0).is_some() {
+        return Vec::default();
+    }
+    if color_call_text(child.nod).is_some() {
+        return Vec::default();
+    }
+    arrange(child.nod, child.kind)
+}
+
+fn layout_for(nod: &ParseNode, kind: Kind) -> Option<&'static Vec<OpLayoutParam>> {
+    let name = match &nod.term {
+        ParseTerm::Ident(val) => val,
+        _ => return None,
+    };
+    match kind {
+        Kind::Op => {
+            get_op3_layout(name).or_else(|| get_op1_layout(name))
+                .map(|(layout, _)| layout)
+        },
+        Kind::Param => get_param_layout(name).map(|(layout, _)| layout),
+        Kind::GradStop => Some(&get_gradstop_layout().0),
+        Kind::KeyFrame => Some(&get_keyframe_layout().0),
+        Kind::PulseShape => get_envelope_layout(name).map(|(layout, _)| layout),
+        _ => None,
+    }
+}
+
+fn slot_kind(ptype: &OpLayoutType) -> Kind {
+    match ptype {
+        OpLayoutType::Op1 | OpLayoutType::Op3 | OpLayoutType::Operand => Kind::Op,
+        OpLayoutType::Param | OpLayoutType::Number => Kind::Param,
+        OpLayoutType::GradStop => Kind::GradStop,
+        OpLayoutType::KeyFrame => Kind::KeyFrame,
+        OpLayoutType::PulseShape => Kind::PulseShape,
+        OpLayoutType::Color | OpLayoutType::Wave | OpLayoutType::Ease | OpLayoutType::LoopMode | OpLayoutType::Kernel | OpLayoutType::Edge | OpLayoutType::Blend => Kind::Leaf,
+    }
+}
+
+// Label a node's children canonically, keeping the order they were
+// written in. Slots named "_1", "_2"... are written positionally when
+// that would fill the same slot, as is an unlabelled first child; any
+// other child gets its slot's label. Nodes we can't match up are left
+// as they were written.
+fn arrange(nod: &ParseNode, kind: Kind) -> Vec<Child<'_>> {
+    let asis = |kind: Kind| -> Vec<Child> {
+        nod.params.items.iter().map(|item| {
+            Child { label: item.key.clone(), nod: item, kind: kind }
+        }).collect()
+    };
+
+    if is_arith(nod) && kind == Kind::Param {
+        return asis(Kind::Param);
+    }
+    let layout = match layout_for(nod, kind) {
+        Some(layout) => layout,
+        None => return asis(Kind::Unknown),
+    };
+    let pmap = match match_children(nod, layout) {
+        Ok(pmap) => pmap,
+        Err(_) => return asis(Kind::Unknown),
+    };
+    let mut slots: Vec<(&String, &usize)> = pmap.iter().collect();
+    slots.sort_by_key(|(_, ix)| **ix);
+
+    // Follow match_children: an unlabelled child fills the first unused
+    // slot (repeating slots are never used up).
+    let mut used = vec![false; layout.len()];
+    let mut res: Vec<Child> = Vec::default();
+    for (slot, ix) in slots {
+        let pos = match slot_pos(layout, slot) {
+            Some(pos) => pos,
+            None => return asis(Kind::Unknown),
+        };
+        let param = &layout[pos];
+        let item = &nod.params.items[*ix];
+        let canpos = used.iter().position(|val| !val) == Some(pos);
+        let leading = res.is_empty() && item.key.is_none();
+        let positional = canpos && (param.name.starts_with('_') || leading);
+        res.push(Child {
+            label: if positional { None } else { Some(param.name.clone()) },
+            nod: item,
+            kind: slot_kind(&param.ptype),
+        });
+        if !param.repeating {
+            used[pos] = true;
+        }
+    }
+    res
+}
+
+// The layout index for a slot name. Repeating slots are numbered: "_2",
+// "stop3".
+fn slot_pos(layout: &[OpLayoutParam], slot: &str) -> Option<usize> {
+    for (pos, param) in layout.iter().enumerate() {
+        if param.name == slot {
+            return Some(pos);
+        }
+        if param.repeating {
+            if let Some(rest) = slot.strip_prefix(param.name.as_str()) {
+                if !rest.is_empty() && rest.chars().all(|ch| ch.is_ascii_digit()) {
+                    return Some(pos);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn is_arith(nod: &ParseNode) -> bool {
+    match &nod.term {
+        ParseTerm::Ident(val) => {
+            matches!(val.as_str(), "sum" | "sub" | "mul" | "div" | "mod" | "neg")
+        },
+        _ => false,
+    }
+}
+
+// Arithmetic params as infix: "0.5 + wavecycle(sine, period=4) * 0.1".
+// Returns None if this isn't arithmetic, or can't be written that way.
+// The outer precedence is that of the surrounding operator; the text is
+// parenthesized if it binds more loosely.
+fn expr_text(nod: &ParseNode, outer: usize) -> Option<String> {
+    if !is_arith(nod) || !nod.leading.is_empty() {
+        return None;
+    }
+    let name = match &nod.term {
+        ParseTerm::Ident(val) => val.as_str(),
+        _ => return None,
+    };
+    let args = &nod.params.items;
+    if args.iter().any(|item| item.key.is_some() || !item.leading.is_empty()) {
+        return None;
+    }
+
+    let (text, prec) = match (name, args.len()) {
+        ("neg", 1) => {
+            (format!("-{}", operand_text(&args[0], 3)?), 3)
+        },
+        ("sum", count) if count >= 2 => {
+            let mut parts: Vec<String> = Vec::default();
+            parts.push(operand_text(&args[0], 1)?);
+            for arg in &args[1..] {
+                parts.push(operand_text(arg, 2)?);
+            }
+            (parts.join(" + "), 1)
+        },
+        ("sub", 2) => {
+            (format!("{} - {}", operand_text(&args[0], 1)?, operand_text(&args[1], 2)?), 1)
+        },
+        ("mul", 2) | ("div", 2) | ("mod", 2) => {
+            let op = match name {
+                "mul" => "*",
+                "div" => "/",
+                _ => "%",
+            };
+            (format!("{} {} {}", operand_text(&args[0], 2)?, op, operand_text(&args[1], 3)?), 2)
+        },
+        _ => return None,
+    };
+
+    if prec < outer {
+        return Some(format!("({})", text));
+    }
+    Some(text)
+}
+
+// One operand of an infix expression. Other params become calls:
+// "wavecycle(sine, period=4)".
+fn operand_text(nod: &ParseNode, outer: usize) -> Option<String> {
+    if !nod.leading.is_empty() {
+        return None;
+    }
+    if is_arith(nod) {
+        return expr_text(nod, outer);
+    }
+    match &nod.term {
+        ParseTerm::Number(val) => {
+            if *val < 0.0 && outer > 1 {
+                return Some(format!("({})", val));
+            }
+            Some(format!("{}", val))
+        },
+        ParseTerm::Ident(val) => {
+            if nod.params.items.is_empty() {
+                return Some(val.to_string());
+            }
+            // Only simple calls; anything deeper stays a block.
+            if nod.params.items.iter().any(|item| !item.params.items.is_empty()) {
+                return None;
+            }
+            let mut parts: Vec<String> = Vec::default();
+            for child in arrange(nod, Kind::Param) {
+                let text = match child.kind {
+                    Kind::Param => operand_text(child.nod, 0)?,
+                    _ => {
+                        if !child.nod.params.items.is_empty() {
+                            return None;
+                        }
+                        term_text(&child.nod.term)
+                    },
+                };
+                match child.label {
+                    Some(label) => parts.push(format!("{}={}", label, text)),
+                    None => parts.push(text),
+                }
+            }
+            Some(format!("{}({})", val, parts.join(", ")))
+        },
+        _ => None,
+    }
+}
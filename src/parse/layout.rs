@@ -1,26 +1,34 @@
-use std::fmt;
 use std::collections::HashMap;
 use lazy_static::lazy_static;
 
 use crate::op::{Op1Def, Op3Def};
-use crate::op::GradStop;
+use crate::op::{GradStop, BlurKernel, EdgeMode, BlendMode};
 use crate::pixel::Pix;
-use crate::waves::WaveShape;
-use crate::pulser::Pulser;
-use crate::param::{Param,ParamDef};
-use crate::parse::tree::{ParseTerm, ParseNode};
+use crate::waves::{WaveShape, Easing};
+use crate::pulser::{Pulser, PulseShape, Envelope};
+use crate::param::{Param, ParamDef, SampleMode, KeyFrame, LoopMode};
+use crate::parse::tree::ParseNode;
+use crate::parse::diag::Diagnostic;
 use crate::parse::ParseContext;
 use crate::parse::BuildOp;
-use crate::parse::{parse_for_op1, parse_for_op3, parse_for_number, parse_for_color, parse_for_waveshape, parse_for_param, parse_for_gradstop};
+use crate::parse::{parse_for_op1, parse_for_op3, parse_for_number, parse_for_color, parse_for_waveshape, parse_for_param, parse_for_gradstop, parse_for_sample, parse_for_keyframe, parse_for_easing, parse_for_loopmode, parse_for_pulseshape, parse_for_blurkernel, parse_for_edgemode, parse_for_blendmode, parse_for_operand};
 
 pub enum OpLayoutType {
     Op1,
     Op3,
+    Operand, // an op1, or a param for the whole strip
     Number,
     Color,
     Param,
     GradStop,
     Wave,
+    KeyFrame,
+    Ease,
+    LoopMode,
+    PulseShape,
+    Kernel,
+    Edge,
+    Blend,
 }
 
 pub struct OpLayoutParam {
@@ -59,28 +67,62 @@ impl OpLayoutParam {
     }
 }
 
-type BuildFuncParam = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<Param, String>;
-type BuildFuncGradStop = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<GradStop, String>;
-type BuildFuncOp1 = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<BuildOp, String>;
-type BuildFuncOp3 = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<BuildOp, String>;
+type BuildFuncParam = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<Param, Diagnostic>;
+type BuildFuncGradStop = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<GradStop, Diagnostic>;
+type BuildFuncKeyFrame = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<(f32, Param, Easing), Diagnostic>;
+type BuildFuncEnvelope = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<PulseShape, Diagnostic>;
+type BuildFuncOp1 = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<BuildOp, Diagnostic>;
+type BuildFuncOp3 = fn(&mut ParseContext, &ParseNode, &HashMap<String, usize>)->Result<BuildOp, Diagnostic>;
 
-pub fn get_waveshape(val: &str) -> Option<&WaveShape> {
+pub fn get_waveshape(val: &str) -> Option<&'static WaveShape> {
     return WAVESHAPELAYOUT.get(val.to_lowercase().as_str());
 }
 
+// An easing is one of the curves below, or any waveshape.
+pub fn get_easing(val: &str) -> Option<Easing> {
+    if let Some(ease) = EASINGLAYOUT.get(val.to_lowercase().as_str()) {
+        return Some(*ease);
+    }
+    return get_waveshape(val).map(|shape| Easing::Wave(*shape));
+}
+
+pub fn get_loopmode(val: &str) -> Option<&'static LoopMode> {
+    return LOOPMODELAYOUT.get(val.to_lowercase().as_str());
+}
+
+pub fn get_blurkernel(val: &str) -> Option<&'static BlurKernel> {
+    return BLURKERNELLAYOUT.get(val.to_lowercase().as_str());
+}
+
+pub fn get_edgemode(val: &str) -> Option<&'static EdgeMode> {
+    return EDGEMODELAYOUT.get(val.to_lowercase().as_str());
+}
+
+pub fn get_blendmode(val: &str) -> Option<&'static BlendMode> {
+    return BLENDMODELAYOUT.get(val.to_lowercase().as_str());
+}
+
+pub fn get_keyframe_layout() -> &'static (Vec<OpLayoutParam>, BuildFuncKeyFrame) {
+    return &KEYFRAMELAYOUT;
+}
+
+pub fn get_envelope_layout(val: &str) -> Option<&'static (Vec<OpLayoutParam>, BuildFuncEnvelope)> {
+    return ENVELOPELAYOUT.get(val.to_lowercase().as_str());
+}
+
 pub fn get_gradstop_layout() -> &'static (Vec<OpLayoutParam>, BuildFuncGradStop) {
     return &GRADSTOPLAYOUT;
 }
 
-pub fn get_param_layout(val: &str) -> Option<&(Vec<OpLayoutParam>, BuildFuncParam)> {
+pub fn get_param_layout(val: &str) -> Option<&'static (Vec<OpLayoutParam>, BuildFuncParam)> {
     return PARAMLAYOUT.get(val.to_lowercase().as_str());
 }
 
-pub fn get_op1_layout(val: &str) -> Option<&(Vec<OpLayoutParam>, BuildFuncOp1)> {
+pub fn get_op1_layout(val: &str) -> Option<&'static (Vec<OpLayoutParam>, BuildFuncOp1)> {
     return OP1LAYOUT.get(val.to_lowercase().as_str());
 }
 
-pub fn get_op3_layout(val: &str) -> Option<&(Vec<OpLayoutParam>, BuildFuncOp3)> {
+pub fn get_op3_layout(val: &str) -> Option<&'static (Vec<OpLayoutParam>, BuildFuncOp3)> {
     return OP3LAYOUT.get(val.to_lowercase().as_str());
 }
 
@@ -90,7 +132,7 @@ lazy_static! {
             OpLayoutParam::param("pos", OpLayoutType::Number),
             OpLayoutParam::param("color", OpLayoutType::Color),
         ],
-         |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<GradStop, String> {
+         |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<GradStop, Diagnostic> {
              let pos = parse_for_number(parsectx, &nod.params.items[pmap["pos"]])?;
              let color = parse_for_color(parsectx, &nod.params.items[pmap["color"]])?;
              Ok(GradStop { pos:pos, color:color })
@@ -112,6 +154,121 @@ lazy_static! {
         ])
     };
     
+    static ref KEYFRAMELAYOUT: (Vec<OpLayoutParam>, BuildFuncKeyFrame) = {
+        (vec![
+            OpLayoutParam::param("time", OpLayoutType::Number),
+            OpLayoutParam::param("value", OpLayoutType::Param),
+            OpLayoutParam::param_optional("ease", OpLayoutType::Ease),
+        ],
+         |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<(f32, Param, Easing), Diagnostic> {
+             let time = parse_for_number(parsectx, &nod.params.items[pmap["time"]])?;
+             if !time.is_finite() {
+                 return Err(Diagnostic::new(&nod.params.items[pmap["time"]].loc, format!("key time must be a finite number: {}", time)));
+             }
+             let value = parse_for_param(parsectx, &nod.params.items[pmap["value"]])?;
+             let ease = match pmap.get("ease") {
+                 Some(val) => parse_for_easing(parsectx, &nod.params.items[*val])?,
+                 None => Easing::Linear,
+             };
+             Ok((time, value, ease))
+         } as BuildFuncKeyFrame)
+    };
+
+    static ref EASINGLAYOUT: HashMap<&'static str, Easing> = {
+        HashMap::from([
+            ("linear", Easing::Linear),
+            ("cubicin", Easing::CubicIn),
+            ("cubicout", Easing::CubicOut),
+            ("cubicinout", Easing::CubicInOut),
+            ("step", Easing::Step),
+        ])
+    };
+
+    static ref LOOPMODELAYOUT: HashMap<&'static str, LoopMode> = {
+        HashMap::from([
+            ("once", LoopMode::Once),
+            ("loop", LoopMode::Loop),
+            ("pingpong", LoopMode::PingPong),
+        ])
+    };
+    
+    static ref BLURKERNELLAYOUT: HashMap<&'static str, BlurKernel> = {
+        HashMap::from([
+            ("box", BlurKernel::Box),
+            ("gaussian", BlurKernel::Gaussian),
+        ])
+    };
+
+    static ref EDGEMODELAYOUT: HashMap<&'static str, EdgeMode> = {
+        HashMap::from([
+            ("wrap", EdgeMode::Wrap),
+            ("clamp", EdgeMode::Clamp),
+            ("zero", EdgeMode::Zero),
+        ])
+    };
+
+    static ref BLENDMODELAYOUT: HashMap<&'static str, BlendMode> = {
+        HashMap::from([
+            ("screen", BlendMode::Screen),
+            ("multiply", BlendMode::Multiply),
+            ("overlay", BlendMode::Overlay),
+            ("add", BlendMode::Add),
+            ("subtract", BlendMode::Subtract),
+            ("difference", BlendMode::Difference),
+            ("lighten", BlendMode::Lighten),
+            ("darken", BlendMode::Darken),
+            ("over", BlendMode::Over),
+        ])
+    };
+    
+    static ref ENVELOPELAYOUT: HashMap<&'static str, (Vec<OpLayoutParam>, BuildFuncEnvelope)> = {
+        let mut map = HashMap::new();
+
+        map.insert(
+            "adsr",
+            (vec![
+                OpLayoutParam::param_optional("attack", OpLayoutType::Param),
+                OpLayoutParam::param_optional("decay", OpLayoutType::Param),
+                OpLayoutParam::param_optional("sustain", OpLayoutType::Param),
+                OpLayoutParam::param_optional("hold", OpLayoutType::Param),
+                OpLayoutParam::param_optional("release", OpLayoutType::Param),
+                OpLayoutParam::param_optional("attackease", OpLayoutType::Ease),
+                OpLayoutParam::param_optional("decayease", OpLayoutType::Ease),
+                OpLayoutParam::param_optional("releaseease", OpLayoutType::Ease),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<PulseShape, Diagnostic> {
+                 let mut env = Envelope::new();
+                 if let Some(val) = pmap.get("attack") {
+                     env.attack = parse_for_param(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("decay") {
+                     env.decay = parse_for_param(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("sustain") {
+                     env.sustain = parse_for_param(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("hold") {
+                     env.hold = parse_for_param(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("release") {
+                     env.release = parse_for_param(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("attackease") {
+                     env.attackease = parse_for_easing(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("decayease") {
+                     env.decayease = parse_for_easing(parsectx, &nod.params.items[*val])?;
+                 }
+                 if let Some(val) = pmap.get("releaseease") {
+                     env.releaseease = parse_for_easing(parsectx, &nod.params.items[*val])?;
+                 }
+                 Ok(PulseShape::Envelope(env))
+             } as BuildFuncEnvelope)
+        );
+
+        map
+    };
+
     static ref PARAMLAYOUT: HashMap<&'static str, (Vec<OpLayoutParam>, BuildFuncParam)> = {
         let mut map = HashMap::new();
         
@@ -120,7 +277,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Number),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let val = parse_for_number(parsectx, &nod.params.items[pmap["_1"]])?;
                  Ok(Param::new(ParamDef::Constant(val)))
              } as BuildFuncParam)
@@ -129,10 +286,10 @@ lazy_static! {
         map.insert(
             "randflat",
             (vec![
-                OpLayoutParam::param("min", OpLayoutType::Number),
-                OpLayoutParam::param("max", OpLayoutType::Number),
+                OpLayoutParam::param("min", OpLayoutType::Param),
+                OpLayoutParam::param("max", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let min = parse_for_param(parsectx, &nod.params.items[pmap["min"]])?;
                  let max = parse_for_param(parsectx, &nod.params.items[pmap["max"]])?;
                  let pdef = ParamDef::RandFlat(0, 1);
@@ -143,10 +300,10 @@ lazy_static! {
         map.insert(
             "randnorm",
             (vec![
-                OpLayoutParam::param_optional("mean", OpLayoutType::Number),
-                OpLayoutParam::param_optional("stdev", OpLayoutType::Number),
+                OpLayoutParam::param_optional("mean", OpLayoutType::Param),
+                OpLayoutParam::param_optional("stdev", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let mean = match pmap.get("mean") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
                      None => Param::newconst(0.5),
@@ -163,10 +320,10 @@ lazy_static! {
         map.insert(
             "changing",
             (vec![
-                OpLayoutParam::param("start", OpLayoutType::Number),
-                OpLayoutParam::param("velocity", OpLayoutType::Number),
+                OpLayoutParam::param("start", OpLayoutType::Param),
+                OpLayoutParam::param("velocity", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let start = parse_for_param(parsectx, &nod.params.items[pmap["start"]])?;
                  let velocity = parse_for_param(parsectx, &nod.params.items[pmap["velocity"]])?;
                  let pdef = ParamDef::Changing(0, 1);
@@ -178,11 +335,11 @@ lazy_static! {
             "wave",
             (vec![
                 OpLayoutParam::param("shape", OpLayoutType::Wave),
-                OpLayoutParam::param_optional("min", OpLayoutType::Number),
-                OpLayoutParam::param_optional("max", OpLayoutType::Number),
-                OpLayoutParam::param_optional("duration", OpLayoutType::Number),
+                OpLayoutParam::param_optional("min", OpLayoutType::Param),
+                OpLayoutParam::param_optional("max", OpLayoutType::Param),
+                OpLayoutParam::param_optional("duration", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let shape = parse_for_waveshape(parsectx, &nod.params.items[pmap["shape"]])?;
                  let min = match pmap.get("min") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -205,12 +362,12 @@ lazy_static! {
             "wavecycle",
             (vec![
                 OpLayoutParam::param("shape", OpLayoutType::Wave),
-                OpLayoutParam::param_optional("min", OpLayoutType::Number),
-                OpLayoutParam::param_optional("max", OpLayoutType::Number),
-                OpLayoutParam::param_optional("period", OpLayoutType::Number),
-                OpLayoutParam::param_optional("offset", OpLayoutType::Number),
+                OpLayoutParam::param_optional("min", OpLayoutType::Param),
+                OpLayoutParam::param_optional("max", OpLayoutType::Param),
+                OpLayoutParam::param_optional("period", OpLayoutType::Param),
+                OpLayoutParam::param_optional("offset", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let shape = parse_for_waveshape(parsectx, &nod.params.items[pmap["shape"]])?;
                  let min = match pmap.get("min") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -233,12 +390,134 @@ lazy_static! {
              } as BuildFuncParam)
         );
 
+        map.insert(
+            "keyframes",
+            (vec![
+                OpLayoutParam::param_repeating("key", OpLayoutType::KeyFrame),
+                OpLayoutParam::param_optional("mode", OpLayoutType::LoopMode),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let mut keys: Vec<(f32, Param, Easing)> = Vec::new();
+                 let mut ix = 0;
+                 loop {
+                     ix += 1;
+                     let tempname = format!("key{}", ix);
+                     if let Some(val) = pmap.get(&tempname) {
+                         let key = parse_for_keyframe(parsectx, &nod.params.items[*val])?;
+                         keys.push(key);
+                     }
+                     else {
+                         break;
+                     }
+                 }
+                 if keys.is_empty() {
+                     return Err(Diagnostic::new(&nod.loc, "keyframes needs at least one key".to_string()));
+                 }
+                 let mode = match pmap.get("mode") {
+                     Some(val) => parse_for_loopmode(parsectx, &nod.params.items[*val])?,
+                     None => LoopMode::Once,
+                 };
+                 keys.sort_by(|key1, key2| key1.0.total_cmp(&key2.0));
+                 let mut frames: Vec<KeyFrame> = Vec::new();
+                 let mut args: Vec<Param> = Vec::new();
+                 for (time, value, ease) in keys {
+                     frames.push(KeyFrame { time:time, value:args.len(), ease:ease });
+                     args.push(value);
+                 }
+                 let mut res = Param::new(ParamDef::KeyFrames(frames, mode));
+                 for arg in args {
+                     res = res.addchild(arg);
+                 }
+                 Ok(res)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "randwalk",
+            (vec![
+                OpLayoutParam::param_optional("start", OpLayoutType::Param),
+                OpLayoutParam::param_optional("min", OpLayoutType::Param),
+                OpLayoutParam::param_optional("max", OpLayoutType::Param),
+                OpLayoutParam::param_optional("step", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let start = match pmap.get("start") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.5),
+                 };
+                 let min = match pmap.get("min") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.0),
+                 };
+                 let max = match pmap.get("max") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let step = match pmap.get("step") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.1),
+                 };
+                 let pdef = ParamDef::RandWalk(0, 1, 2, 3);
+                 Ok(Param::new(pdef).addchild(start).addchild(min).addchild(max).addchild(step))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "drift",
+            (vec![
+                OpLayoutParam::param_optional("start", OpLayoutType::Param),
+                OpLayoutParam::param_optional("step", OpLayoutType::Param),
+                OpLayoutParam::param_optional("revert", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let start = match pmap.get("start") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.5),
+                 };
+                 let step = match pmap.get("step") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.1),
+                 };
+                 let revert = match pmap.get("revert") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.0),
+                 };
+                 let pdef = ParamDef::Drift(0, 1, 2);
+                 Ok(Param::new(pdef).addchild(start).addchild(step).addchild(revert))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "smoothnoise",
+            (vec![
+                OpLayoutParam::param_optional("min", OpLayoutType::Param),
+                OpLayoutParam::param_optional("max", OpLayoutType::Param),
+                OpLayoutParam::param_optional("period", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let min = match pmap.get("min") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.0),
+                 };
+                 let max = match pmap.get("max") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let period = match pmap.get("period") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let pdef = ParamDef::SmoothNoise(0, 1, 2);
+                 Ok(Param::new(pdef).addchild(min).addchild(max).addchild(period))
+             } as BuildFuncParam)
+        );
+
         map.insert(
             "sum",
             (vec![
-                OpLayoutParam::param_repeating("_", OpLayoutType::Op1),
+                OpLayoutParam::param_repeating("_", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let pdef = ParamDef::Sum((0..pmap.len()).collect());
                  let mut par = Param::new(pdef);
                  for ix in 0..pmap.len() {
@@ -250,12 +529,213 @@ lazy_static! {
              } as BuildFuncParam)
         );
         
+        map.insert(
+            "sub",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+                OpLayoutParam::param("_2", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val1 = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let val2 = parse_for_param(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let pdef = ParamDef::Sub(0, 1);
+                 Ok(Param::new(pdef).addchild(val1).addchild(val2))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "mul",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+                OpLayoutParam::param("_2", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val1 = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let val2 = parse_for_param(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let pdef = ParamDef::Mul(0, 1);
+                 Ok(Param::new(pdef).addchild(val1).addchild(val2))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "div",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+                OpLayoutParam::param("_2", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val1 = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let val2 = parse_for_param(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let pdef = ParamDef::Div(0, 1);
+                 Ok(Param::new(pdef).addchild(val1).addchild(val2))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "mod",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+                OpLayoutParam::param("_2", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val1 = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let val2 = parse_for_param(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let pdef = ParamDef::Mod(0, 1);
+                 Ok(Param::new(pdef).addchild(val1).addchild(val2))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "neg",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let pdef = ParamDef::Neg(0);
+                 Ok(Param::new(pdef).addchild(val))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "abs",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let pdef = ParamDef::Abs(0);
+                 Ok(Param::new(pdef).addchild(val))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "min",
+            (vec![
+                OpLayoutParam::param_repeating("_", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 if pmap.is_empty() {
+                     return Err(Diagnostic::new(&nod.loc, "min needs at least one value".to_string()));
+                 }
+                 let pdef = ParamDef::Min((0..pmap.len()).collect());
+                 let mut par = Param::new(pdef);
+                 for ix in 0..pmap.len() {
+                     let tempname = format!("_{}", 1+ix);
+                     let val = parse_for_param(parsectx, &nod.params.items[pmap[&tempname]])?;
+                     par = par.addchild(val);
+                 }
+                 Ok(par)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "max",
+            (vec![
+                OpLayoutParam::param_repeating("_", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 if pmap.is_empty() {
+                     return Err(Diagnostic::new(&nod.loc, "max needs at least one value".to_string()));
+                 }
+                 let pdef = ParamDef::Max((0..pmap.len()).collect());
+                 let mut par = Param::new(pdef);
+                 for ix in 0..pmap.len() {
+                     let tempname = format!("_{}", 1+ix);
+                     let val = parse_for_param(parsectx, &nod.params.items[pmap[&tempname]])?;
+                     par = par.addchild(val);
+                 }
+                 Ok(par)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "clamp",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Param),
+                OpLayoutParam::param_optional("min", OpLayoutType::Param),
+                OpLayoutParam::param_optional("max", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 let val = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let min = match pmap.get("min") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.0),
+                 };
+                 let max = match pmap.get("max") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let pdef = ParamDef::Clamp(0, 1, 2);
+                 Ok(Param::new(pdef).addchild(val).addchild(min).addchild(max))
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "sample",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("pos", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 parse_for_sample(parsectx, nod, pmap, SampleMode::At)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "samplemean",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("start", OpLayoutType::Param),
+                OpLayoutParam::param_optional("end", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 parse_for_sample(parsectx, nod, pmap, SampleMode::Mean)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "samplemax",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("start", OpLayoutType::Param),
+                OpLayoutParam::param_optional("end", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 parse_for_sample(parsectx, nod, pmap, SampleMode::Max)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "samplemin",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("start", OpLayoutType::Param),
+                OpLayoutParam::param_optional("end", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 parse_for_sample(parsectx, nod, pmap, SampleMode::Min)
+             } as BuildFuncParam)
+        );
+
+        map.insert(
+            "brightest",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("start", OpLayoutType::Param),
+                OpLayoutParam::param_optional("end", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
+                 parse_for_sample(parsectx, nod, pmap, SampleMode::Brightest)
+             } as BuildFuncParam)
+        );
+
         map.insert(
             "quote",
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<Param, Diagnostic> {
                  let val = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
                  let pdef = ParamDef::Quote(0);
                  Ok(Param::new(pdef).addchild(val))
@@ -273,7 +753,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Number),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let val = parse_for_number(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op1Def::Constant(val);
                  Ok(BuildOp::new1(op))
@@ -285,7 +765,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let val = parse_for_param(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op1Def::Param(val);
                  Ok(BuildOp::new1(op))
@@ -301,7 +781,7 @@ lazy_static! {
                 OpLayoutParam::param_optional("pos", OpLayoutType::Param),
                 OpLayoutParam::param_optional("width", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let shape = parse_for_waveshape(parsectx, &nod.params.items[pmap["shape"]])?;
                  let min = match pmap.get("min") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -333,7 +813,7 @@ lazy_static! {
                 OpLayoutParam::param_optional("pos", OpLayoutType::Param),
                 OpLayoutParam::param_optional("period", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let shape = parse_for_waveshape(parsectx, &nod.params.items[pmap["shape"]])?;
                  let min = match pmap.get("min") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -361,7 +841,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op1Def::Invert();
                  Ok(BuildOp::new1(op).addchild1(subop))
@@ -373,7 +853,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op1Def::Brightness();
                  Ok(BuildOp::new1(op).addchild3(subop))
@@ -388,10 +868,10 @@ lazy_static! {
                 OpLayoutParam::param_optional("duration", OpLayoutType::Param),
                 OpLayoutParam::param_optional("pos", OpLayoutType::Param),
                 OpLayoutParam::param_optional("width", OpLayoutType::Param),
-                OpLayoutParam::param_optional("spaceshape", OpLayoutType::Wave),
-                OpLayoutParam::param_optional("timeshape", OpLayoutType::Wave),
+                OpLayoutParam::param_optional("spaceshape", OpLayoutType::PulseShape),
+                OpLayoutParam::param_optional("timeshape", OpLayoutType::PulseShape),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let mut pulser = Pulser::new();
                  if let Some(val) = pmap.get("interval") {
                      pulser.interval = parse_for_param(parsectx, &nod.params.items[*val])?;
@@ -410,10 +890,10 @@ lazy_static! {
                      pulser.countlimit = Some(limit);
                  }
                  if let Some(val) = pmap.get("spaceshape") {
-                     pulser.spaceshape = parse_for_waveshape(parsectx, &nod.params.items[*val])?;
+                     pulser.spaceshape = parse_for_pulseshape(parsectx, &nod.params.items[*val])?;
                  }
                  if let Some(val) = pmap.get("timeshape") {
-                     pulser.timeshape = parse_for_waveshape(parsectx, &nod.params.items[*val])?;
+                     pulser.timeshape = parse_for_pulseshape(parsectx, &nod.params.items[*val])?;
                  }
                  let op = Op1Def::Pulser(pulser);
                  Ok(BuildOp::new1(op))
@@ -426,7 +906,7 @@ lazy_static! {
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
                 OpLayoutParam::param_optional("halflife", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let halflife = match pmap.get("halflife") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -442,7 +922,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op1Def::TimeDelta();
                  Ok(BuildOp::new1(op).addchild1(subop))
@@ -454,7 +934,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
                 OpLayoutParam::param_repeating("stop", OpLayoutType::Number),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                 let mut stops: Vec<f32> = Vec::new();
                 let mut ix = 0;
@@ -480,7 +960,7 @@ lazy_static! {
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
                 OpLayoutParam::param("_2", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let subop2 = parse_for_op1(parsectx, &nod.params.items[pmap["_2"]])?;
                  let op = Op1Def::Mul();
@@ -488,12 +968,153 @@ lazy_static! {
              } as BuildFuncOp1)
         );
         
+        map.insert(
+            "sub",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param("_2", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let subop2 = parse_for_operand(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let op = Op1Def::Sub();
+                 Ok(BuildOp::new1(op).addchild1(subop1).addchild1(subop2))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "div",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param("_2", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let subop2 = parse_for_operand(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let op = Op1Def::Div();
+                 Ok(BuildOp::new1(op).addchild1(subop1).addchild1(subop2))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "absdiff",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param("_2", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let subop2 = parse_for_operand(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let op = Op1Def::AbsDiff();
+                 Ok(BuildOp::new1(op).addchild1(subop1).addchild1(subop2))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "pow",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param("_2", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let subop2 = parse_for_operand(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let op = Op1Def::Pow();
+                 Ok(BuildOp::new1(op).addchild1(subop1).addchild1(subop2))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "mod",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param("_2", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let subop2 = parse_for_operand(parsectx, &nod.params.items[pmap["_2"]])?;
+                 let op = Op1Def::Mod();
+                 Ok(BuildOp::new1(op).addchild1(subop1).addchild1(subop2))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "step",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(0.5)),
+                 };
+                 let op = Op1Def::Step();
+                 Ok(BuildOp::new1(op).addchild1(subop).addchild1(edge))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "smoothstep",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("min", OpLayoutType::Operand),
+                OpLayoutParam::param_optional("max", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let min = match pmap.get("min") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(0.0)),
+                 };
+                 let max = match pmap.get("max") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(1.0)),
+                 };
+                 let op = Op1Def::SmoothStep();
+                 Ok(BuildOp::new1(op).addchild1(subop).addchild1(min).addchild1(max))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "remap",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("inmin", OpLayoutType::Operand),
+                OpLayoutParam::param_optional("inmax", OpLayoutType::Operand),
+                OpLayoutParam::param_optional("outmin", OpLayoutType::Operand),
+                OpLayoutParam::param_optional("outmax", OpLayoutType::Operand),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let inmin = match pmap.get("inmin") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(0.0)),
+                 };
+                 let inmax = match pmap.get("inmax") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(1.0)),
+                 };
+                 let outmin = match pmap.get("outmin") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(0.0)),
+                 };
+                 let outmax = match pmap.get("outmax") {
+                     Some(val) => parse_for_operand(parsectx, &nod.params.items[*val])?,
+                     None => BuildOp::new1(Op1Def::Constant(1.0)),
+                 };
+                 let op = Op1Def::Remap();
+                 Ok(BuildOp::new1(op).addchild1(subop).addchild1(inmin).addchild1(inmax).addchild1(outmin).addchild1(outmax))
+             } as BuildFuncOp1)
+        );
+        
         map.insert(
             "sum",
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op1Def::Sum();
                  let mut bop = BuildOp::new1(op);
                  for ix in 0..pmap.len() {
@@ -510,7 +1131,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op1Def::Mean();
                  let mut bop = BuildOp::new1(op);
                  for ix in 0..pmap.len() {
@@ -527,7 +1148,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op1Def::Min();
                  let mut bop = BuildOp::new1(op);
                  for ix in 0..pmap.len() {
@@ -544,7 +1165,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op1Def::Max();
                  let mut bop = BuildOp::new1(op);
                  for ix in 0..pmap.len() {
@@ -563,7 +1184,7 @@ lazy_static! {
                 OpLayoutParam::param_optional("min", OpLayoutType::Param),
                 OpLayoutParam::param_optional("max", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let min = match pmap.get("min") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -584,7 +1205,7 @@ lazy_static! {
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
                 OpLayoutParam::param_optional("offset", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let offset = match pmap.get("offset") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -602,7 +1223,7 @@ lazy_static! {
                 OpLayoutParam::param_optional("offset", OpLayoutType::Param),
                 OpLayoutParam::param_optional("halflife", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                  let offset = match pmap.get("offset") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -617,6 +1238,106 @@ lazy_static! {
              } as BuildFuncOp1)
         );
         
+        map.insert(
+            "blur",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("radius", OpLayoutType::Param),
+                OpLayoutParam::param_optional("kernel", OpLayoutType::Kernel),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Edge),
+                OpLayoutParam::param_repeating("weight", OpLayoutType::Number),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let kernel = parse_blur_kernel(parsectx, nod, pmap)?;
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_edgemode(parsectx, &nod.params.items[*val])?,
+                     None => EdgeMode::Wrap,
+                 };
+                 let radius = match pmap.get("radius") {
+                     Some(val) => Some(parse_for_param(parsectx, &nod.params.items[*val])?),
+                     None => None,
+                 };
+                 let op = Op1Def::Blur(kernel, edge, radius);
+                 Ok(BuildOp::new1(op).addchild1(subop))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "mirror",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("center", OpLayoutType::Param),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Edge),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let center = match pmap.get("center") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.5),
+                 };
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_edgemode(parsectx, &nod.params.items[*val])?,
+                     None => EdgeMode::Wrap,
+                 };
+                 let op = Op1Def::Mirror(center, edge);
+                 Ok(BuildOp::new1(op).addchild1(subop))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "reverse",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let op = Op1Def::Reverse();
+                 Ok(BuildOp::new1(op).addchild1(subop))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "tile",
+            (vec![
+                OpLayoutParam::param("count", OpLayoutType::Param),
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let count = parse_for_param(parsectx, &nod.params.items[pmap["count"]])?;
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let op = Op1Def::Tile(count);
+                 Ok(BuildOp::new1(op).addchild1(subop))
+             } as BuildFuncOp1)
+        );
+        
+        map.insert(
+            "zoom",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op1),
+                OpLayoutParam::param_optional("scale", OpLayoutType::Param),
+                OpLayoutParam::param_optional("center", OpLayoutType::Param),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Edge),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let scale = match pmap.get("scale") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let center = match pmap.get("center") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.5),
+                 };
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_edgemode(parsectx, &nod.params.items[*val])?,
+                     None => EdgeMode::Wrap,
+                 };
+                 let op = Op1Def::Zoom(scale, center, edge);
+                 Ok(BuildOp::new1(op).addchild1(subop))
+             } as BuildFuncOp1)
+        );
+        
         map.insert(
             "noise",
             (vec![
@@ -625,7 +1346,7 @@ lazy_static! {
                 OpLayoutParam::param_optional("offset", OpLayoutType::Param),
                 OpLayoutParam::param_optional("max", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let grain = match pmap.get("grain") {
                      Some(val) => parse_for_number(parsectx, &nod.params.items[*val])?,
                      None => 32.0,
@@ -658,7 +1379,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Color),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let pix = parse_for_color(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op3Def::Constant(pix);
                  Ok(BuildOp::new3(op))
@@ -670,7 +1391,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                  let op = Op3Def::Invert();
                  Ok(BuildOp::new3(op).addchild3(subop))
@@ -681,7 +1402,7 @@ lazy_static! {
             "grey",
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                 let op = Op3Def::Grey();
                 Ok(BuildOp::new3(op).addchild1(subop))
@@ -694,7 +1415,7 @@ lazy_static! {
                 OpLayoutParam::param("r", OpLayoutType::Op1),
                 OpLayoutParam::param("g", OpLayoutType::Op1),
                 OpLayoutParam::param("b", OpLayoutType::Op1),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["r"]])?;
                 let subop2 = parse_for_op1(parsectx, &nod.params.items[pmap["g"]])?;
                 let subop3 = parse_for_op1(parsectx, &nod.params.items[pmap["b"]])?;
@@ -709,7 +1430,7 @@ lazy_static! {
                 OpLayoutParam::param("h", OpLayoutType::Op1),
                 OpLayoutParam::param("s", OpLayoutType::Op1),
                 OpLayoutParam::param("v", OpLayoutType::Op1),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop1 = parse_for_op1(parsectx, &nod.params.items[pmap["h"]])?;
                 let subop2 = parse_for_op1(parsectx, &nod.params.items[pmap["s"]])?;
                 let subop3 = parse_for_op1(parsectx, &nod.params.items[pmap["v"]])?;
@@ -722,7 +1443,7 @@ lazy_static! {
             "hsvtorgb",
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                 let op = Op3Def::HSVToRGB();
                 Ok(BuildOp::new3(op).addchild3(subop))
@@ -733,7 +1454,7 @@ lazy_static! {
             "rgbtohsv",
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                 let op = Op3Def::RGBToHSV();
                 Ok(BuildOp::new3(op).addchild3(subop))
@@ -745,7 +1466,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
                 OpLayoutParam::param_repeating("stop", OpLayoutType::Color),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                 let mut stops: Vec<Pix<f32>> = Vec::new();
                 let mut ix = 0;
@@ -770,7 +1491,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param("_1", OpLayoutType::Op1),
                 OpLayoutParam::param_repeating("stop", OpLayoutType::GradStop),
-            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+            ], |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                 let subop = parse_for_op1(parsectx, &nod.params.items[pmap["_1"]])?;
                 let mut stops: Vec<GradStop> = Vec::new();
                 let mut ix = 0;
@@ -797,7 +1518,7 @@ lazy_static! {
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
                 OpLayoutParam::param("_2", OpLayoutType::Op1),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop1 = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                  let subop2 = parse_for_op1(parsectx, &nod.params.items[pmap["_2"]])?;
                  let op = Op3Def::MulS();
@@ -810,7 +1531,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op3Def::Sum();
                  let mut bop = BuildOp::new3(op);
                  for ix in 0..pmap.len() {
@@ -827,7 +1548,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op3Def::Mean();
                  let mut bop = BuildOp::new3(op);
                  for ix in 0..pmap.len() {
@@ -844,7 +1565,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op3Def::Min();
                  let mut bop = BuildOp::new3(op);
                  for ix in 0..pmap.len() {
@@ -861,7 +1582,7 @@ lazy_static! {
             (vec![
                 OpLayoutParam::param_repeating("_", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let op = Op3Def::Max();
                  let mut bop = BuildOp::new3(op);
                  for ix in 0..pmap.len() {
@@ -880,7 +1601,7 @@ lazy_static! {
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
                 OpLayoutParam::param("_2", OpLayoutType::Op3),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop1 = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                  let subop2 = parse_for_op3(parsectx, &nod.params.items[pmap["_2"]])?;
                  let subopm = parse_for_op1(parsectx, &nod.params.items[pmap["mask"]])?;
@@ -889,6 +1610,31 @@ lazy_static! {
              } as BuildFuncOp3)
         );
         
+        map.insert(
+            "blend",
+            (vec![
+                OpLayoutParam::param("mode", OpLayoutType::Blend),
+                OpLayoutParam::param_repeating("_", OpLayoutType::Op3),
+                OpLayoutParam::param_optional("opacity", OpLayoutType::Param),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let mode = parse_for_blendmode(parsectx, &nod.params.items[pmap["mode"]])?;
+                 let opacity = match pmap.get("opacity") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let op = Op3Def::Blend(mode, opacity);
+                 let mut bop = BuildOp::new3(op);
+                 let mut ix = 1;
+                 while let Some(val) = pmap.get(&format!("_{}", ix)) {
+                     let subop = parse_for_op3(parsectx, &nod.params.items[*val])?;
+                     bop = bop.addchild3(subop);
+                     ix += 1;
+                 }
+                 Ok(bop)
+             } as BuildFuncOp3)
+        );
+        
         map.insert(
             "mask",
             (vec![
@@ -897,7 +1643,7 @@ lazy_static! {
                 OpLayoutParam::param("_2", OpLayoutType::Op3),
                 OpLayoutParam::param_optional("threshold", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop1 = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                  let subop2 = parse_for_op3(parsectx, &nod.params.items[pmap["_2"]])?;
                  let subopm = parse_for_op1(parsectx, &nod.params.items[pmap["mask"]])?;
@@ -916,7 +1662,7 @@ lazy_static! {
                 OpLayoutParam::param("_1", OpLayoutType::Op3),
                 OpLayoutParam::param_optional("offset", OpLayoutType::Param),
             ],
-             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, String> {
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
                  let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
                  let offset = match pmap.get("offset") {
                      Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
@@ -927,6 +1673,136 @@ lazy_static! {
              } as BuildFuncOp3)
         );
         
+        map.insert(
+            "blur",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op3),
+                OpLayoutParam::param_optional("radius", OpLayoutType::Param),
+                OpLayoutParam::param_optional("kernel", OpLayoutType::Kernel),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Edge),
+                OpLayoutParam::param_repeating("weight", OpLayoutType::Number),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let kernel = parse_blur_kernel(parsectx, nod, pmap)?;
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_edgemode(parsectx, &nod.params.items[*val])?,
+                     None => EdgeMode::Wrap,
+                 };
+                 let radius = match pmap.get("radius") {
+                     Some(val) => Some(parse_for_param(parsectx, &nod.params.items[*val])?),
+                     None => None,
+                 };
+                 let op = Op3Def::Blur(kernel, edge, radius);
+                 Ok(BuildOp::new3(op).addchild3(subop))
+             } as BuildFuncOp3)
+        );
+        
+        map.insert(
+            "mirror",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op3),
+                OpLayoutParam::param_optional("center", OpLayoutType::Param),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Edge),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let center = match pmap.get("center") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.5),
+                 };
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_edgemode(parsectx, &nod.params.items[*val])?,
+                     None => EdgeMode::Wrap,
+                 };
+                 let op = Op3Def::Mirror(center, edge);
+                 Ok(BuildOp::new3(op).addchild3(subop))
+             } as BuildFuncOp3)
+        );
+        
+        map.insert(
+            "reverse",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op3),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let op = Op3Def::Reverse();
+                 Ok(BuildOp::new3(op).addchild3(subop))
+             } as BuildFuncOp3)
+        );
+        
+        map.insert(
+            "tile",
+            (vec![
+                OpLayoutParam::param("count", OpLayoutType::Param),
+                OpLayoutParam::param("_1", OpLayoutType::Op3),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let count = parse_for_param(parsectx, &nod.params.items[pmap["count"]])?;
+                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let op = Op3Def::Tile(count);
+                 Ok(BuildOp::new3(op).addchild3(subop))
+             } as BuildFuncOp3)
+        );
+        
+        map.insert(
+            "zoom",
+            (vec![
+                OpLayoutParam::param("_1", OpLayoutType::Op3),
+                OpLayoutParam::param_optional("scale", OpLayoutType::Param),
+                OpLayoutParam::param_optional("center", OpLayoutType::Param),
+                OpLayoutParam::param_optional("edge", OpLayoutType::Edge),
+            ],
+             |parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>| -> Result<BuildOp, Diagnostic> {
+                 let subop = parse_for_op3(parsectx, &nod.params.items[pmap["_1"]])?;
+                 let scale = match pmap.get("scale") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(1.0),
+                 };
+                 let center = match pmap.get("center") {
+                     Some(val) => parse_for_param(parsectx, &nod.params.items[*val])?,
+                     None => Param::newconst(0.5),
+                 };
+                 let edge = match pmap.get("edge") {
+                     Some(val) => parse_for_edgemode(parsectx, &nod.params.items[*val])?,
+                     None => EdgeMode::Wrap,
+                 };
+                 let op = Op3Def::Zoom(scale, center, edge);
+                 Ok(BuildOp::new3(op).addchild3(subop))
+             } as BuildFuncOp3)
+        );
+        
         map
     };
 }
+
+// A blur's kernel: named, or given as weights (an odd number, centered).
+fn parse_blur_kernel(parsectx: &mut ParseContext, nod: &ParseNode, pmap: &HashMap<String, usize>) -> Result<BlurKernel, Diagnostic> {
+    let mut weights: Vec<f32> = Vec::new();
+    let mut ix = 0;
+    loop {
+        ix += 1;
+        let tempname = format!("weight{}", ix);
+        if let Some(val) = pmap.get(&tempname) {
+            let scal = parse_for_number(parsectx, &nod.params.items[*val])?;
+            weights.push(scal);
+        }
+        else {
+            break;
+        }
+    }
+    if weights.is_empty() {
+        return match pmap.get("kernel") {
+            Some(val) => parse_for_blurkernel(parsectx, &nod.params.items[*val]),
+            None => Ok(BlurKernel::Box),
+        };
+    }
+    if pmap.contains_key("kernel") {
+        return Err(Diagnostic::new(&nod.loc, "blur takes a kernel or weights, not both".to_string()));
+    }
+    if weights.len().is_multiple_of(2) {
+        return Err(Diagnostic::new(&nod.loc, "blur weights must be an odd number, centered".to_string()));
+    }
+    Ok(BlurKernel::Custom(weights))
+}
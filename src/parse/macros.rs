@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use crate::parse::tree::{ParseItems, ParseNode, ParseTerm};
+use crate::parse::diag::{Diagnostic, ParseError};
+use crate::parse::layout::{get_param_layout, get_op1_layout, get_op3_layout, get_waveshape};
+
+// A macro which expands deeper than this is assumed to be recursive.
+const MAXDEPTH: usize = 64;
+
+struct Macro {
+    args: Vec<String>,
+    body: ParseNode,
+}
+
+// Remove all "define" items from the list, and then expand every
+// invocation of them. Invocations look like any other op or param:
+//    streak: $F0C, 1.5
+//    streak(color=$F0C, interval=1.5)
+pub fn expand_macros(itemls: &mut ParseItems) -> Result<(), ParseError> {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut diags: Vec<Diagnostic> = Vec::default();
+
+    let mut remaining: Vec<ParseNode> = Vec::default();
+    for item in itemls.items.drain(..) {
+        if let ParseTerm::Define(name, args) = &item.term {
+            if macros.contains_key(name) {
+                diags.push(Diagnostic::new(&item.loc, format!("macro has two definitions: {}", name)));
+                continue;
+            }
+            if isbuiltin(name) {
+                diags.push(Diagnostic::new(&item.loc, format!("macro name is already an op or param: {}", name)));
+                continue;
+            }
+            match item.params.items.first() {
+                Some(body) => {
+                    macros.insert(name.to_string(), Macro {
+                        args: args.clone(),
+                        body: body.clone(),
+                    });
+                },
+                None => {
+                    diags.push(Diagnostic::new(&item.loc, format!("define has no body: {}", name)));
+                },
+            }
+        }
+        else {
+            remaining.push(item);
+        }
+    }
+
+    for item in remaining.iter_mut() {
+        if let Err(diag) = expand_node(item, &macros, 0) {
+            diags.push(diag);
+        }
+    }
+    itemls.items = remaining;
+
+    if !diags.is_empty() {
+        return Err(ParseError { diags: diags });
+    }
+    return Ok(());
+}
+
+fn isbuiltin(name: &str) -> bool {
+    return get_param_layout(name).is_some()
+        || get_op1_layout(name).is_some()
+        || get_op3_layout(name).is_some()
+        || get_waveshape(name).is_some();
+}
+
+fn expand_node(nod: &mut ParseNode, macros: &HashMap<String, Macro>, depth: usize) -> Result<(), Diagnostic> {
+    if let ParseTerm::Ident(name) = &nod.term {
+        if let Some(mac) = macros.get(name) {
+            if depth >= MAXDEPTH {
+                return Err(Diagnostic::new(&nod.loc, format!("macro expansion too deep (recursive define?): {}", name)));
+            }
+            let argmap = bind_args(nod, name, mac)?;
+            let mut body = mac.body.clone();
+            substitute(&mut body, &argmap)?;
+            // The invocation keeps its own label and position.
+            if nod.key.is_some() {
+                body.key = nod.key.clone();
+            }
+            body.indent = nod.indent;
+            *nod = body;
+            return expand_node(nod, macros, depth+1);
+        }
+    }
+
+    for item in nod.params.items.iter_mut() {
+        expand_node(item, macros, depth)?;
+    }
+    return Ok(());
+}
+
+// Match the invocation's params against the macro's arguments. Labelled
+// params go to the named argument; the rest fill the others in order.
+fn bind_args<'a>(nod: &'a ParseNode, name: &str, mac: &Macro) -> Result<HashMap<String, &'a ParseNode>, Diagnostic> {
+    let mut argmap: HashMap<String, &ParseNode> = HashMap::new();
+
+    for item in &nod.params.items {
+        if let Some(key) = &item.key {
+            if !mac.args.contains(key) {
+                return Err(Diagnostic::new(&item.loc, format!("macro {} has no argument: {}", name, key)));
+            }
+            if argmap.contains_key(key) {
+                return Err(Diagnostic::new(&item.loc, format!("macro {} argument given twice: {}", name, key)));
+            }
+            argmap.insert(key.to_string(), item);
+        }
+    }
+
+    let mut argpos = 0;
+    for item in &nod.params.items {
+        if item.key.is_some() {
+            continue;
+        }
+        while argpos < mac.args.len() && argmap.contains_key(&mac.args[argpos]) {
+            argpos += 1;
+        }
+        if argpos >= mac.args.len() {
+            return Err(Diagnostic::new(&item.loc, format!("too many arguments for macro {}", name)));
+        }
+        argmap.insert(mac.args[argpos].to_string(), item);
+        argpos += 1;
+    }
+
+    for arg in &mac.args {
+        if !argmap.contains_key(arg) {
+            return Err(Diagnostic::new(&nod.loc, format!("macro {} missing argument: {}", name, arg)));
+        }
+    }
+
+    return Ok(argmap);
+}
+
+// Replace each bare argument name in the body with the argument's value.
+// The replaced node keeps its label ("width=width").
+fn substitute(nod: &mut ParseNode, argmap: &HashMap<String, &ParseNode>) -> Result<(), Diagnostic> {
+    if let ParseTerm::Ident(name) = &nod.term {
+        if let Some(arg) = argmap.get(name) {
+            if !nod.params.items.is_empty() {
+                return Err(Diagnostic::new(&nod.loc, format!("macro argument cannot have params: {}", name)));
+            }
+            nod.term = arg.term.clone();
+            nod.params = arg.params.clone();
+            nod.loc = arg.loc.clone();
+            return Ok(());
+        }
+    }
+
+    for item in nod.params.items.iter_mut() {
+        substitute(item, argmap)?;
+    }
+    return Ok(());
+}
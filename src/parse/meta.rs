@@ -0,0 +1,83 @@
+use crate::script::ScriptMeta;
+use crate::parse::tree::{split_unit, parseterm, ParseTerm};
+use crate::parse::diag::{SourceLoc, Diagnostic, ParseError};
+
+// Read the "#!" header lines of a script. Each is "#! key: value".
+pub fn parse_meta(header: &Vec<(String, SourceLoc)>) -> Result<ScriptMeta, ParseError> {
+    let mut meta = ScriptMeta::default();
+    let mut diags: Vec<Diagnostic> = Vec::default();
+
+    for (line, loc) in header {
+        if let Err(msg) = parse_meta_line(&mut meta, line) {
+            diags.push(Diagnostic::new(loc, msg));
+        }
+    }
+
+    if !diags.is_empty() {
+        return Err(ParseError { diags: diags });
+    }
+    return Ok(meta);
+}
+
+fn parse_meta_line(meta: &mut ScriptMeta, line: &str) -> Result<(), String> {
+    let body = line.trim_start_matches("#!").trim();
+    if body.starts_with('/') {
+        // "#!/usr/bin/env beacon"
+        return Ok(());
+    }
+    let (key, val) = body.split_once(':')
+        .ok_or_else(|| format!("header line must be \"key: value\": {}", body))?;
+    let key = key.trim();
+    let val = val.trim();
+    if val.is_empty() {
+        return Err(format!("header has no value: {}", key));
+    }
+
+    match key {
+        "title" => meta.title = Some(val.to_string()),
+        "author" => meta.author = Some(val.to_string()),
+        "description" => meta.description = Some(val.to_string()),
+        "size" => {
+            let num = val.parse::<usize>()
+                .map_err(|_| format!("header size must be a pixel count: {}", val))?;
+            meta.size = Some(num);
+        },
+        "fps" => {
+            let num = val.parse::<u32>()
+                .map_err(|_| format!("header fps must be a whole number: {}", val))?;
+            meta.fps = Some(num);
+        },
+        "seed" => {
+            let num = val.parse::<u64>()
+                .map_err(|_| format!("header seed must be a whole number: {}", val))?;
+            meta.seed = Some(num);
+        },
+        "tags" => {
+            meta.tags = val.split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+        },
+        "duration" => {
+            // Plain seconds, or "90s", "1500ms".
+            let num = match split_unit(val) {
+                Some((num, "s")) => Some(num),
+                Some((num, "ms")) => Some(num / 1000.0),
+                Some(_) => None,
+                None => val.parse::<f32>().ok(),
+            };
+            match num {
+                Some(num) if num > 0.0 => meta.duration = Some(num),
+                _ => return Err(format!("header duration must be a time in seconds: {}", val)),
+            }
+        },
+        "background" => {
+            match parseterm(val) {
+                Ok(ParseTerm::Color(pix)) => meta.background = Some(pix),
+                _ => return Err(format!("header background must be a $color: {}", val)),
+            }
+        },
+        _ => return Err(format!("unknown header key: {}", key)),
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use crate::parse::tree::{ParseTerm, ParseNode};
+use crate::parse::diag::{SourceLoc, Diagnostic, ParseError};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    New,
+    Active,
+    Done,
+}
+
+// Work out the order in which to build the top-level items, so that
+// every variable is built before the items that refer to it. Items can
+// appear in any order in the script.
+//
+// The root is the last unnamed item. (If all are named, it's the last
+// item which nothing refers to.) It always comes last in the list.
+pub fn build_order(items: &Vec<ParseNode>) -> Result<Vec<usize>, ParseError> {
+    let mut diags: Vec<Diagnostic> = Vec::default();
+
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for (ix, item) in items.iter().enumerate() {
+        if let Some(varname) = &item.key {
+            if names.contains_key(varname) {
+                diags.push(Diagnostic::new(&item.loc, format!("variable has two definitions: {}", varname)));
+                continue;
+            }
+            names.insert(varname.to_string(), ix);
+        }
+    }
+
+    let mut deps: Vec<Vec<(String, SourceLoc)>> = Vec::default();
+    for item in items {
+        let mut refs: Vec<(String, SourceLoc)> = Vec::default();
+        collect_refs(item, &mut refs);
+        for (varname, loc) in &refs {
+            if !names.contains_key(varname) {
+                diags.push(Diagnostic::new(loc, format!("no such variable: {}", varname)));
+            }
+        }
+        deps.push(refs);
+    }
+
+    if !diags.is_empty() {
+        return Err(ParseError { diags: diags });
+    }
+
+    let mut order: Vec<usize> = Vec::default();
+    if items.is_empty() {
+        return Ok(order);
+    }
+
+    let root = match items.iter().rposition(|item| item.key.is_none()) {
+        Some(ix) => ix,
+        None => {
+            let referenced: Vec<&String> = deps.iter().flatten().map(|(varname, _)| varname).collect();
+            items.iter().rposition(|item| !referenced.contains(&item.key.as_ref().unwrap()))
+                .unwrap_or(items.len()-1)
+        },
+    };
+
+    let mut state = vec![Visit::New; items.len()];
+    let mut stack: Vec<usize> = Vec::default();
+    for ix in 0..items.len() {
+        if ix != root {
+            visit(ix, items, &names, &deps, &mut state, &mut stack, &mut order)?;
+        }
+    }
+    visit(root, items, &names, &deps, &mut state, &mut stack, &mut order)?;
+
+    Ok(order)
+}
+
+// Depth-first, appending each item after the items it depends on.
+fn visit(ix: usize, items: &Vec<ParseNode>, names: &HashMap<String, usize>, deps: &Vec<Vec<(String, SourceLoc)>>, state: &mut Vec<Visit>, stack: &mut Vec<usize>, order: &mut Vec<usize>) -> Result<(), Diagnostic> {
+    if state[ix] == Visit::Done {
+        return Ok(());
+    }
+    state[ix] = Visit::Active;
+    stack.push(ix);
+
+    for (varname, loc) in &deps[ix] {
+        let depix = names[varname];
+        match state[depix] {
+            Visit::Done => {},
+            Visit::New => {
+                visit(depix, items, names, deps, state, stack, order)?;
+            },
+            Visit::Active => {
+                let pos = stack.iter().position(|val| *val == depix).unwrap();
+                let mut path: Vec<&str> = stack[pos..].iter()
+                    .map(|val| items[*val].key.as_deref().unwrap_or("_"))
+                    .collect();
+                path.push(varname);
+                return Err(Diagnostic::new(loc, format!("variable cycle: {}", path.join(" -> "))));
+            },
+        }
+    }
+
+    stack.pop();
+    state[ix] = Visit::Done;
+    order.push(ix);
+    Ok(())
+}
+
+// All the 'var references in a node, in order.
+fn collect_refs(nod: &ParseNode, refs: &mut Vec<(String, SourceLoc)>) {
+    if let ParseTerm::VarName(val) = &nod.term {
+        refs.push((val.to_string(), nod.loc.clone()));
+    }
+    for item in &nod.params.items {
+        collect_refs(item, refs);
+    }
+}
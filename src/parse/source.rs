@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+// Where script text comes from. The parser asks for the main script, and
+// for each included file, by name.
+pub trait ScriptSource {
+    fn open(&self, name: &str) -> Result<Box<dyn BufRead + '_>, String>;
+
+    // A name which is the same for every way of naming one script. This
+    // is how include cycles and repeated includes are spotted. An error
+    // means the script doesn't exist.
+    fn identity(&self, name: &str) -> Result<String, String>;
+
+    // The name of a script included from another. Includes are relative
+    // to the including script.
+    fn resolve(&self, from: &str, incname: &str) -> String {
+        match Path::new(from).parent() {
+            Some(dir) => dir.join(incname).to_string_lossy().to_string(),
+            None => incname.to_string(),
+        }
+    }
+}
+
+// Scripts on disk.
+pub struct FileSource;
+
+impl ScriptSource for FileSource {
+    fn open(&self, name: &str) -> Result<Box<dyn BufRead + '_>, String> {
+        let file = File::open(name)
+            .map_err(|err| format!("{}: {}", name, err))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn identity(&self, name: &str) -> Result<String, String> {
+        let canon = std::fs::canonicalize(name)
+            .map_err(|err| format!("{}: {}", name, err))?;
+        Ok(canon.to_string_lossy().to_string())
+    }
+}
+
+// Scripts held in memory, by name: for tests, or for scripts that arrive
+// some other way than as files.
+pub struct MemSource {
+    scripts: HashMap<String, String>,
+}
+
+impl Default for MemSource {
+    fn default() -> MemSource {
+        MemSource::new()
+    }
+}
+
+impl MemSource {
+    pub fn new() -> MemSource {
+        MemSource {
+            scripts: HashMap::new(),
+        }
+    }
+
+    pub fn add(mut self, name: &str, text: &str) -> MemSource {
+        self.scripts.insert(normalize(name), text.to_string());
+        self
+    }
+}
+
+impl ScriptSource for MemSource {
+    fn open(&self, name: &str) -> Result<Box<dyn BufRead + '_>, String> {
+        match self.scripts.get(&normalize(name)) {
+            Some(text) => Ok(Box::new(Cursor::new(text.as_bytes()))),
+            None => Err(format!("{}: no such script", name)),
+        }
+    }
+
+    fn identity(&self, name: &str) -> Result<String, String> {
+        let key = normalize(name);
+        if !self.scripts.contains_key(&key) {
+            return Err(format!("{}: no such script", name));
+        }
+        Ok(key)
+    }
+}
+
+// Tidy up "dir/./a/../b" to "dir/b", without looking at the disk.
+fn normalize(name: &str) -> String {
+    let mut res = PathBuf::new();
+    for comp in Path::new(name).components() {
+        match comp {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if !res.pop() {
+                    res.push("..");
+                }
+            },
+            _ => res.push(comp),
+        }
+    }
+    res.to_string_lossy().to_string()
+}
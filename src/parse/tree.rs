@@ -1,9 +1,11 @@
 use std::fmt;
-use std::fs::File;
-use std::io::BufReader;
 use std::io::BufRead;
+use std::rc::Rc;
+use std::collections::HashSet;
 
 use crate::pixel::Pix;
+use crate::parse::diag::{SourceLoc, Diagnostic, ParseError};
+use crate::parse::source::ScriptSource;
 
 #[derive(Debug, Clone)]
 pub enum ParseTerm {
@@ -11,6 +13,8 @@ pub enum ParseTerm {
     Color(Pix<f32>),
     Ident(String),
     VarName(String),
+    Define(String, Vec<String>), // name, args
+    ColorDef(String), // name
 }
 
 impl fmt::Display for ParseTerm {
@@ -20,26 +24,41 @@ impl fmt::Display for ParseTerm {
             ParseTerm::Color(pix) => write!(f, "{}", pix.as_hex()),
             ParseTerm::Ident(val) => write!(f, "{}", val),
             ParseTerm::VarName(val) => write!(f, "'{}", val),
+            ParseTerm::Define(name, args) => write!(f, "define {}({})", name, args.join(", ")),
+            ParseTerm::ColorDef(name) => write!(f, "color {}", name),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct ParseItems {
     pub items: Vec<ParseNode>,
+    pub trailing: Vec<String>, // comments after the last item
+    pub header: Vec<(String, SourceLoc)>, // "#!" lines at the top of the script
 }
 
+#[derive(Clone)]
 pub struct ParseNode {
     pub key: Option<String>,
     pub term: ParseTerm,
     pub params: Box<ParseItems>,
     pub indent: Option<usize>,
-    pub linenum: usize,
+    pub loc: SourceLoc,
+    pub leading: Vec<String>, // comment lines before this one ("" for a blank line)
+}
+
+impl Default for ParseItems {
+    fn default() -> ParseItems {
+        ParseItems::new()
+    }
 }
 
 impl ParseItems {
     pub fn new() -> ParseItems {
         ParseItems {
             items: Vec::default(),
+            trailing: Vec::default(),
+            header: Vec::default(),
         }
     }
 
@@ -80,13 +99,14 @@ impl ParseItems {
 }
 
 impl ParseNode {
-    pub fn new(key: Option<&str>, term: ParseTerm, indent: Option<usize>, linenum: usize) -> ParseNode {
+    pub fn new(key: Option<&str>, term: ParseTerm, indent: Option<usize>, loc: SourceLoc) -> ParseNode {
         ParseNode {
             key: key.map(|val| val.to_string()),
             term: term,
             params: Box::new(ParseItems::new()),
             indent: indent,
-            linenum: linenum,
+            loc: loc,
+            leading: Vec::default(),
         }
     }
 
@@ -100,15 +120,105 @@ impl ParseNode {
     }
 }
 
-fn labelterm(val: &str) -> Result<(Option<&str>, ParseTerm), String> {
-    let (label, term) = val.split_once('=')
-        .map_or_else(
-            || (None, val.trim()),
-            |(keyv, restv)| (Some(keyv.trim()), restv.trim()));
+// Split "key=term" at the first top-level '='. An '=' inside parentheses
+// belongs to a call argument, not to this term.
+fn splitlabel(val: &str) -> (Option<&str>, &str) {
+    match find_toplevel(val, &['=']) {
+        Some(pos) => (Some(val[..pos].trim()), val[pos+1..].trim()),
+        None => (None, val.trim()),
+    }
+}
+
+// Find the first of the given characters which is not inside parentheses.
+fn find_toplevel(val: &str, chars: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (pos, ch) in val.char_indices() {
+        if ch == '(' {
+            depth += 1;
+        }
+        else if ch == ')' {
+            if depth > 0 {
+                depth -= 1;
+            }
+        }
+        else if depth == 0 && chars.contains(&ch) {
+            return Some(pos);
+        }
+    }
+    None
+}
+
+fn isident(val: &str) -> bool {
+    val.starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+        && val.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+// "define name(arg, arg)" or just "define name".
+fn parsedefine(term: &str) -> Result<ParseTerm, String> {
+    let (name, args) = match term.split_once('(') {
+        None => (term.trim(), ""),
+        Some((name, rest)) => {
+            let args = rest.trim_end().strip_suffix(')')
+                .ok_or_else(|| format!("missing ')' in define: {}", term))?;
+            (name.trim(), args)
+        },
+    };
+    if !isident(name) {
+        return Err(format!("bad define name: {}", name));
+    }
+    
+    let mut argls: Vec<String> = Vec::default();
+    if !args.trim().is_empty() {
+        for arg in args.split(',') {
+            let arg = arg.trim();
+            if !isident(arg) {
+                return Err(format!("bad define argument: {}", arg));
+            }
+            if argls.iter().any(|val| val == arg) {
+                return Err(format!("define argument appears twice: {}", arg));
+            }
+            argls.push(arg.to_string());
+        }
+    }
+    
+    Ok(ParseTerm::Define(name.to_string(), argls))
+}
+
+// Split a number with a unit suffix: "10px", "50%", "2700K", "1e-3s".
+// The number is the longest prefix that parses, so an exponent is not
+// mistaken for the start of the unit.
+pub fn split_unit(term: &str) -> Option<(f32, &str)> {
+    let numlen = term.find(|ch: char| !(ch.is_ascii_digit() || matches!(ch, '.' | '+' | '-' | 'e' | 'E')))
+        .unwrap_or(term.len());
+    for pos in (1..=numlen).rev() {
+        let (num, unit) = term.split_at(pos);
+        if unit.is_empty() || (unit != "%" && !unit.chars().all(|ch| ch.is_ascii_alphabetic())) {
+            continue;
+        }
+        if let Ok(val) = num.parse::<f32>() {
+            return Some((val, unit));
+        }
+    }
+    None
+}
 
+fn isexpression(term: &str) -> bool {
+    if term.parse::<f32>().is_ok() || split_unit(term).is_some() {
+        return false;
+    }
+    term.contains(['+', '-', '*', '/', '%', '(', ')'])
+}
+
+pub fn parseterm(term: &str) -> Result<ParseTerm, String> {
     if term.starts_with(['-', '+', '.', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9']) {    
         if let Ok(float) = term.parse::<f32>() {
-            return Ok((label, ParseTerm::Number(float)));
+            return Ok(ParseTerm::Number(float));
+        }
+
+        // A number with a unit, "10px" or "2700K". These are resolved
+        // later; see units.rs and colors.rs.
+        if split_unit(term).is_some() {
+            return Ok(ParseTerm::Ident(term.to_string()));
         }
 
         return Err(format!("bad numeric constant: {}", term));
@@ -121,7 +231,7 @@ fn labelterm(val: &str) -> Result<(Option<&str>, ParseTerm), String> {
                 u32::from_str_radix(&term[2..3], 16),
                 u32::from_str_radix(&term[3..4], 16)
             ) {
-                return Ok((label, ParseTerm::Color(Pix::new((rval as f32)/15.0, (gval as f32)/15.0, (bval as f32)/15.0))));
+                return Ok(ParseTerm::Color(Pix::new((rval as f32)/15.0, (gval as f32)/15.0, (bval as f32)/15.0)));
             }
         }
         
@@ -131,95 +241,718 @@ fn labelterm(val: &str) -> Result<(Option<&str>, ParseTerm), String> {
                 u32::from_str_radix(&term[3..5], 16),
                 u32::from_str_radix(&term[5..7], 16)
             ) {
-                return Ok((label, ParseTerm::Color(Pix::new((rval as f32)/255.0, (gval as f32)/255.0, (bval as f32)/255.0))));
+                return Ok(ParseTerm::Color(Pix::new((rval as f32)/255.0, (gval as f32)/255.0, (bval as f32)/255.0)));
+            }
+        }
+
+        if term.len() == 5 {
+            if let (Ok(rval), Ok(gval), Ok(bval), Ok(aval)) = (
+                u32::from_str_radix(&term[1..2], 16),
+                u32::from_str_radix(&term[2..3], 16),
+                u32::from_str_radix(&term[3..4], 16),
+                u32::from_str_radix(&term[4..5], 16)
+            ) {
+                return Ok(ParseTerm::Color(Pix::with_alpha((rval as f32)/15.0, (gval as f32)/15.0, (bval as f32)/15.0, (aval as f32)/15.0)));
+            }
+        }
+        
+        if term.len() == 9 {
+            if let (Ok(rval), Ok(gval), Ok(bval), Ok(aval)) = (
+                u32::from_str_radix(&term[1..3], 16),
+                u32::from_str_radix(&term[3..5], 16),
+                u32::from_str_radix(&term[5..7], 16),
+                u32::from_str_radix(&term[7..9], 16)
+            ) {
+                return Ok(ParseTerm::Color(Pix::with_alpha((rval as f32)/255.0, (gval as f32)/255.0, (bval as f32)/255.0, (aval as f32)/255.0)));
             }
         }
 
         return Err(format!("bad $color constant: {}", term));
     }
 
-    if term.starts_with('\'') {
-        if term.len() <= 1 {
+    if let Some(name) = term.strip_prefix('\'') {
+        if name.is_empty() {
             return Err(format!("empty variable name: {}", term));
         }
-        return Ok((label, ParseTerm::VarName(term[1..].to_string())));
+        return Ok(ParseTerm::VarName(name.to_string()));
     }
     
-    return Ok((label, ParseTerm::Ident(term.to_string())));
+    return Ok(ParseTerm::Ident(term.to_string()));
 }
 
-pub fn parse_tree(filename: &str) -> Result<ParseItems, String> {
-    let file = File::open(filename)
-        .map_err(|err| {
-            format!("{}: {}", filename, err.to_string())
-        })?;
-    let lineiter = BufReader::new(file).lines();
+// Parse one comma-or-colon-delimited term into a node. Most terms are
+// a single node; an arithmetic expression becomes a subtree.
+fn termnode(val: &str, indent: Option<usize>, loc: &SourceLoc) -> Result<ParseNode, Diagnostic> {
+    let (label, term) = splitlabel(val);
+    let termloc = loc.sub(offset_in(val, term), term.len());
+    if let Some(rest) = term.strip_prefix("define ") {
+        if label.is_some() {
+            return Err(Diagnostic::new(loc, format!("define cannot have a label: {}", val)));
+        }
+        let termval = parsedefine(rest)
+            .map_err(|msg| Diagnostic::new(&termloc, msg))?;
+        return Ok(ParseNode::new(None, termval, indent, loc.clone()));
+    }
+    if let Some(rest) = term.strip_prefix("color ") {
+        if label.is_some() {
+            return Err(Diagnostic::new(loc, format!("color constant cannot have a label: {}", val)));
+        }
+        let name = rest.trim();
+        if !isident(name) {
+            return Err(Diagnostic::new(&termloc, format!("bad color constant name: {}", name)));
+        }
+        return Ok(ParseNode::new(None, ParseTerm::ColorDef(name.to_string()), indent, loc.clone()));
+    }
+    
+    if isexpression(term) {
+        let mut nod = ExprParser::new(term, termloc).parse()?;
+        nod.key = label.map(|val| val.to_string());
+        nod.indent = indent;
+        nod.loc = loc.clone();
+        return Ok(nod);
+    }
+    
+    let termval = parseterm(term)
+        .map_err(|msg| Diagnostic::new(&termloc, msg))?;
+    Ok(ParseNode::new(label, termval, indent, loc.clone()))
+}
+
+// The byte offset of a subslice within the string it was sliced from.
+fn offset_in(outer: &str, inner: &str) -> usize {
+    (inner.as_ptr() as usize) - (outer.as_ptr() as usize)
+}
+
+// Infix expressions: "0.5 + wavecycle(sine, period=4) * 0.1".
+// These are lowered into the same node tree that the indented syntax
+// produces. Operators become nodes named "sum", "sub", "mul", "div",
+// "mod", "neg"; a call "name(a, key=b)" becomes node "name" with those
+// children.
+struct ExprParser<'a> {
+    src: &'a str,
+    pos: usize,
+    loc: SourceLoc, // location of src
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(src: &'a str, loc: SourceLoc) -> ExprParser<'a> {
+        ExprParser {
+            src: src,
+            pos: 0,
+            loc: loc,
+        }
+    }
+
+    fn parse(&mut self) -> Result<ParseNode, Diagnostic> {
+        let nod = self.parse_sum()?;
+        self.skipspace();
+        if let Some(ch) = self.peek() {
+            return Err(self.err(format!("unexpected '{}' in expression", ch)));
+        }
+        Ok(nod)
+    }
+
+    // An error at the current position.
+    fn err(&self, msg: String) -> Diagnostic {
+        Diagnostic::new(&self.loc.sub(self.pos, 1), msg)
+    }
+
+    // The location from start to the current position.
+    fn locfrom(&self, start: usize) -> SourceLoc {
+        self.loc.sub(start, self.pos - start)
+    }
+
+    fn skipspace(&mut self) {
+        while let Some(ch) = self.peek() {
+            if !ch.is_whitespace() {
+                break;
+            }
+            self.pos += ch.len_utf8();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn scan_while<F>(&mut self, func: F) -> &'a str
+    where F: Fn(char) -> bool {
+        let start = self.pos;
+        while let Some(ch) = self.peek() {
+            if !func(ch) {
+                break;
+            }
+            self.pos += ch.len_utf8();
+        }
+        &self.src[start..self.pos]
+    }
+
+    fn opnode(&self, name: &str, args: Vec<ParseNode>, start: usize) -> ParseNode {
+        let mut nod = ParseNode::new(None, ParseTerm::Ident(name.to_string()), None, self.locfrom(start));
+        nod.params.items = args;
+        nod
+    }
+
+    fn parse_sum(&mut self) -> Result<ParseNode, Diagnostic> {
+        self.skipspace();
+        let start = self.pos;
+        let mut left = self.parse_product()?;
+        // A run of "a + b + c" collects into one sum node.
+        let mut insum = false;
+        loop {
+            self.skipspace();
+            match self.peek() {
+                Some('+') => {
+                    self.pos += 1;
+                    let right = self.parse_product()?;
+                    if insum {
+                        left.params.items.push(right);
+                        left.loc = self.locfrom(start);
+                    }
+                    else {
+                        left = self.opnode("sum", vec![left, right], start);
+                        insum = true;
+                    }
+                },
+                Some('-') => {
+                    self.pos += 1;
+                    let right = self.parse_product()?;
+                    left = self.opnode("sub", vec![left, right], start);
+                    insum = false;
+                },
+                _ => break,
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_product(&mut self) -> Result<ParseNode, Diagnostic> {
+        self.skipspace();
+        let start = self.pos;
+        let mut left = self.parse_unary()?;
+        loop {
+            self.skipspace();
+            let name = match self.peek() {
+                Some('*') => "mul",
+                Some('/') => "div",
+                Some('%') => "mod",
+                _ => break,
+            };
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = self.opnode(name, vec![left, right], start);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<ParseNode, Diagnostic> {
+        self.skipspace();
+        let start = self.pos;
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                let nod = self.parse_unary()?;
+                if let ParseTerm::Number(val) = nod.term {
+                    return Ok(ParseNode::new(None, ParseTerm::Number(-val), None, self.locfrom(start)));
+                }
+                Ok(self.opnode("neg", vec![nod], start))
+            },
+            Some('+') => {
+                self.pos += 1;
+                self.parse_unary()
+            },
+            _ => self.parse_atom(),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<ParseNode, Diagnostic> {
+        self.skipspace();
+        let ch = match self.peek() {
+            Some(ch) => ch,
+            None => return Err(self.err("incomplete expression".to_string())),
+        };
+
+        if ch == '(' {
+            self.pos += 1;
+            let nod = self.parse_sum()?;
+            self.skipspace();
+            if self.peek() != Some(')') {
+                return Err(self.err("missing ')' in expression".to_string()));
+            }
+            self.pos += 1;
+            return Ok(nod);
+        }
+
+        if ch.is_ascii_digit() || ch == '.' {
+            let start = self.pos;
+            self.scan_while(|ch| ch.is_ascii_digit() || ch == '.');
+            if let Some('e') | Some('E') = self.peek() {
+                let mark = self.pos;
+                self.pos += 1;
+                if let Some('-') | Some('+') = self.peek() {
+                    self.pos += 1;
+                }
+                if self.scan_while(|ch| ch.is_ascii_digit()).is_empty() {
+                    self.pos = mark;
+                }
+            }
+            // A unit suffix. "50%" is a percentage, but "50 % 7" and
+            // "50%7" are arithmetic.
+            self.scan_while(|ch| ch.is_ascii_alphabetic());
+            if self.peek() == Some('%') {
+                let next = self.src[self.pos+1..].chars().next();
+                if !next.is_some_and(|ch| ch.is_alphanumeric() || ch == '.' || ch == '(' || ch == '$' || ch == '\'') {
+                    self.pos += 1;
+                }
+            }
+            let term = parseterm(&self.src[start..self.pos])
+                .map_err(|msg| Diagnostic::new(&self.locfrom(start), msg))?;
+            return Ok(ParseNode::new(None, term, None, self.locfrom(start)));
+        }
+
+        if ch == '$' || ch == '\'' {
+            let start = self.pos;
+            self.pos += 1;
+            self.scan_while(|ch| ch.is_alphanumeric() || ch == '_');
+            let term = parseterm(&self.src[start..self.pos])
+                .map_err(|msg| Diagnostic::new(&self.locfrom(start), msg))?;
+            return Ok(ParseNode::new(None, term, None, self.locfrom(start)));
+        }
+
+        if ch.is_alphabetic() || ch == '_' {
+            let start = self.pos;
+            let name = self.scan_while(|ch| ch.is_alphanumeric() || ch == '_');
+            let mut nod = ParseNode::new(None, ParseTerm::Ident(name.to_string()), None, self.locfrom(start));
+            self.skipspace();
+            if self.peek() == Some('(') {
+                self.pos += 1;
+                nod.params.items = self.parse_args()?;
+                nod.loc = self.locfrom(start);
+            }
+            return Ok(nod);
+        }
+
+        Err(self.err(format!("unexpected '{}' in expression", ch)))
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<ParseNode>, Diagnostic> {
+        let mut args: Vec<ParseNode> = Vec::default();
+        self.skipspace();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+            return Ok(args);
+        }
+        
+        loop {
+            // An argument may be labelled: "period=4".
+            self.skipspace();
+            let mark = self.pos;
+            let name = self.scan_while(|ch| ch.is_alphanumeric() || ch == '_');
+            self.skipspace();
+            let key = if !name.is_empty() && self.peek() == Some('=') {
+                self.pos += 1;
+                Some(name)
+            }
+            else {
+                self.pos = mark;
+                None
+            };
+            
+            let mut nod = self.parse_sum()?;
+            if key.is_some() {
+                nod.key = key.map(|val| val.to_string());
+                nod.loc = self.locfrom(mark);
+            }
+            args.push(nod);
+            
+            self.skipspace();
+            match self.peek() {
+                Some(',') => {
+                    self.pos += 1;
+                },
+                Some(')') => {
+                    self.pos += 1;
+                    return Ok(args);
+                },
+                _ => {
+                    return Err(self.err("missing ')' in expression".to_string()));
+                },
+            }
+        }
+    }
+}
+
+// Files seen while following include directives, and the errors
+// found along the way.
+struct IncludeState<'a> {
+    source: &'a dyn ScriptSource,
+    stack: Vec<(String, String)>, // (identity, name) being read, outermost first
+    seen: HashSet<String>,
+    sources: Vec<String>,
+    diags: Vec<Diagnostic>,
+    expand: bool, // if false, include lines are kept as comments
+}
+
+impl<'a> IncludeState<'a> {
+    fn new(source: &'a dyn ScriptSource, expand: bool) -> IncludeState<'a> {
+        IncludeState {
+            source: source,
+            stack: Vec::default(),
+            seen: HashSet::new(),
+            sources: Vec::default(),
+            diags: Vec::default(),
+            expand: expand,
+        }
+    }
+}
+
+// Parse a script into a tree. The names of all scripts read (the given
+// one plus any includes) are appended to sources. Errors are collected
+// for every line, not just the first bad one.
+pub fn parse_tree(source: &dyn ScriptSource, name: &str, sources: &mut Vec<String>) -> Result<ParseItems, ParseError> {
+    let mut incstate = IncludeState::new(source, true);
+    let fileref: Rc<str> = Rc::from(name);
+    let scriptitems = parse_file(&fileref, &mut incstate)
+        .map_err(ParseError::from)?;
+    if !incstate.diags.is_empty() {
+        return Err(ParseError { diags: incstate.diags });
+    }
+    sources.append(&mut incstate.sources);
+    Ok(scriptitems)
+}
+
+// Parse a script from a reader into a tree. The name is used in error
+// messages, and includes are looked up relative to it in the source.
+pub fn parse_tree_reader(reader: &mut dyn BufRead, name: &str, source: &dyn ScriptSource, sources: &mut Vec<String>) -> Result<ParseItems, ParseError> {
+    let mut incstate = IncludeState::new(source, true);
+    let fileref: Rc<str> = Rc::from(name);
+    let scriptitems = parse_lines(&fileref, reader, &mut incstate)
+        .map_err(ParseError::from)?;
+    if !incstate.diags.is_empty() {
+        return Err(ParseError { diags: incstate.diags });
+    }
+    sources.append(&mut incstate.sources);
+    Ok(scriptitems)
+}
+
+// Parse a script into a tree without following includes. The include
+// lines are kept along with the comments, for the formatter.
+pub fn parse_tree_raw(source: &dyn ScriptSource, name: &str) -> Result<ParseItems, ParseError> {
+    let mut incstate = IncludeState::new(source, false);
+    let fileref: Rc<str> = Rc::from(name);
+    let scriptitems = parse_file(&fileref, &mut incstate)
+        .map_err(ParseError::from)?;
+    if !incstate.diags.is_empty() {
+        return Err(ParseError { diags: incstate.diags });
+    }
+    Ok(scriptitems)
+}
+
+fn parse_file(filename: &Rc<str>, incstate: &mut IncludeState) -> Result<ParseItems, Diagnostic> {
+    let source = incstate.source;
+    let mut reader = source.open(filename.as_ref())
+        .map_err(Diagnostic::bare)?;
+    parse_lines(filename, &mut reader, incstate)
+}
+
+fn parse_lines(filename: &Rc<str>, reader: &mut dyn BufRead, incstate: &mut IncludeState) -> Result<ParseItems, Diagnostic> {
+    let lineiter = reader.lines();
+
+    let canon = incstate.source.identity(filename.as_ref())
+        .unwrap_or_else(|_| filename.to_string());
+    incstate.stack.push((canon.clone(), filename.to_string()));
+    incstate.seen.insert(canon);
+    incstate.sources.push(filename.to_string());
 
     let mut scriptitems = ParseItems::new();
     let mut linenum = 0;
+    let mut afterinclude = false;
+    // After a bad line, skip the lines nested under it.
+    let mut skipindent: Option<usize> = None;
+    // Comments and blank lines waiting to be attached to the next node.
+    let mut leading: Vec<String> = Vec::default();
     
     for rline in lineiter {
-        let line = rline.map_err(|err| {
-            format!("{}: {}", filename, err.to_string())
-        })?;
+        let line = match rline {
+            Ok(line) => line,
+            Err(err) => {
+                incstate.diags.push(Diagnostic::bare(format!("{}: {}", filename, err)));
+                break;
+            },
+        };
         linenum += 1;
-        let line = line.trim_end().replace("\t", "    ");
-        let origlen = line.len();
-        let line = line.trim_start();
-        let indent = origlen - line.len();
-        if line.len() == 0 || line.starts_with('#') {
+        let fullline: Rc<str> = Rc::from(line.trim_end().replace("\t", "    "));
+        let line = fullline.trim_start();
+        let indent = fullline.len() - line.len();
+        if line.is_empty() {
+            if leading.last().map_or(!scriptitems.items.is_empty(), |val| !val.is_empty()) {
+                leading.push(String::new());
+            }
+            continue;
+        }
+        let lineloc = SourceLoc::new(filename, linenum, indent, line.len(), &fullline);
+        if line.starts_with('#') {
+            // The header of the main script; in an included file it's
+            // just a comment.
+            if line.starts_with("#!") && incstate.stack.len() == 1 && scriptitems.items.is_empty() {
+                scriptitems.header.push((line.to_string(), lineloc));
+            }
+            leading.push(line.to_string());
             continue;
         }
 
-        let mut lineterms = ParseItems::new();
-        let mut depth = 0;
-        let mut vindent = Some(indent);
-        let mut ltail: &str = line;
-        
-        while ltail.len() > 0 {
-            let mut term: &str;
-            match ltail.find([',', ':']) {
-                None => {
-                    term = ltail;
-                    ltail = "";
-                    let (termkey, termval) = labelterm(term)
-                        .map_err(|msg| format!("{msg} at line {linenum}"))?;
-                    let nod = ParseNode::new(termkey, termval, vindent, linenum);
-                    lineterms.append_at(nod, depth);
+        if let Some(val) = skipindent {
+            if indent > val {
+                continue;
+            }
+            skipindent = None;
+        }
+
+        if afterinclude && indent > 0 {
+            incstate.diags.push(Diagnostic::new(&lineloc, "include cannot have params".to_string()));
+            skipindent = Some(0);
+            continue;
+        }
+        afterinclude = false;
+
+        if let Some(rest) = line.strip_prefix("include ") {
+            if indent > 0 {
+                incstate.diags.push(Diagnostic::new(&lineloc, "include must be at top level".to_string()));
+                continue;
+            }
+            if !incstate.expand {
+                leading.push(line.to_string());
+                afterinclude = true;
+                continue;
+            }
+            let argloc = lineloc.sub(offset_in(line, rest), rest.len());
+            match parse_include(filename, rest.trim(), &argloc, incstate) {
+                Ok(mut incitems) => {
+                    scriptitems.items.append(&mut incitems.items);
+                },
+                Err(diag) => {
+                    incstate.diags.push(diag);
                 },
-                Some(pos) => {
-                    (term, ltail) = ltail.split_at(pos);
-                    term = term.trim();
-                    if term.len() == 0 {
-                        return Err(format!("empty term at line {linenum}"));
-                    }
-                    if ltail.starts_with(',') {
-                        ltail = ltail.get(1..).unwrap().trim();
-                        let (termkey, termval) = labelterm(term)
-                            .map_err(|msg| format!("{msg} at line {linenum}"))?;
-                        let nod = ParseNode::new(termkey, termval, vindent, linenum);
-                        lineterms.append_at(nod, depth);
-                    }
-                    else {
-                        ltail = ltail.get(1..).unwrap().trim();
-                        let (termkey, termval) = labelterm(term)
-                            .map_err(|msg| format!("{msg} at line {linenum}"))?;
-                        let nod = ParseNode::new(termkey, termval, vindent, linenum);
-                        lineterms.append_at(nod, depth);
-                        depth += 1;
-                        vindent = None;
-                    }
-                }
             }
+            afterinclude = true;
+            continue;
+        }
+
+        let res = parse_line(line, indent, &lineloc)
+            .and_then(|mut lineterms| {
+                if let Some(nod) = lineterms.items.first_mut() {
+                    nod.leading = std::mem::take(&mut leading);
+                }
+                scriptitems.append_at_indent(&mut lineterms.items, indent)
+                    .map_err(|msg| Diagnostic::new(&lineloc, msg))
+            });
+        if let Err(diag) = res {
+            incstate.diags.push(diag);
+            skipindent = Some(indent);
         }
-        
-        scriptitems.append_at_indent(&mut lineterms.items, indent)
-            .map_err(|msg| format!("{msg} at line {linenum}"))?;
     }
 
+    incstate.stack.pop();
+
+    while leading.last().is_some_and(|val| val.is_empty()) {
+        leading.pop();
+    }
+    scriptitems.trailing = leading;
+
     //println!("tree:");
     //scriptitems.dump(1);
     
     Ok(scriptitems)
 }
 
+// Split one line into terms. Commas separate siblings; a colon makes the
+// rest of the line children of the previous term.
+fn parse_line(line: &str, indent: usize, lineloc: &SourceLoc) -> Result<ParseItems, Diagnostic> {
+    let mut lineterms = ParseItems::new();
+    let mut depth = 0;
+    let mut vindent = Some(indent);
+    let mut ltail: &str = line;
+    
+    while !ltail.is_empty() {
+        let mut term: &str;
+        match find_toplevel(ltail, &[',', ':']) {
+            None => {
+                term = ltail;
+                ltail = "";
+                let termloc = lineloc.sub(offset_in(line, term), term.len());
+                let nod = termnode(term, vindent, &termloc)?;
+                lineterms.append_at(nod, depth);
+            },
+            Some(pos) => {
+                (term, ltail) = ltail.split_at(pos);
+                term = term.trim();
+                let termloc = lineloc.sub(offset_in(line, term), term.len());
+                if term.is_empty() {
+                    return Err(Diagnostic::new(&lineloc.sub(offset_in(line, ltail), 1), "empty term".to_string()));
+                }
+                if ltail.starts_with(',') {
+                    ltail = ltail.get(1..).unwrap().trim();
+                    let nod = termnode(term, vindent, &termloc)?;
+                    lineterms.append_at(nod, depth);
+                }
+                else {
+                    ltail = ltail.get(1..).unwrap().trim();
+                    let nod = termnode(term, vindent, &termloc)?;
+                    if !nod.params.items.is_empty() {
+                        return Err(Diagnostic::new(&termloc, "expression cannot have params".to_string()));
+                    }
+                    lineterms.append_at(nod, depth);
+                    depth += 1;
+                    vindent = None;
+                }
+            }
+        }
+    }
+
+    Ok(lineterms)
+}
+
+// Read an included file, relative to the including file. Only named
+// variables, defines, and color constants are pulled in; a file already
+// included is skipped.
+fn parse_include(filename: &str, arg: &str, argloc: &SourceLoc, incstate: &mut IncludeState) -> Result<ParseItems, Diagnostic> {
+    let incname = arg.strip_prefix('"').and_then(|val| val.strip_suffix('"'))
+        .ok_or_else(|| Diagnostic::new(argloc, format!("include needs a quoted filename: {}", arg)))?;
+    let incfile = incstate.source.resolve(filename, incname);
+    let canon = incstate.source.identity(&incfile)
+        .map_err(|msg| Diagnostic::new(argloc, msg))?;
+
+    if let Some(pos) = incstate.stack.iter().position(|(path, _)| *path == canon) {
+        let mut names: Vec<&str> = incstate.stack[pos..].iter().map(|(_, name)| name.as_str()).collect();
+        names.push(&incfile);
+        return Err(Diagnostic::new(argloc, format!("include cycle: {}", names.join(" -> "))));
+    }
+    if incstate.seen.contains(&canon) {
+        return Ok(ParseItems::new());
+    }
+
+    let fileref: Rc<str> = Rc::from(incfile.as_str());
+    let mut itemls = parse_file(&fileref, incstate)
+        .map_err(|diag| {
+            match diag.loc {
+                Some(_) => diag,
+                None => Diagnostic::new(argloc, diag.message),
+            }
+        })?;
+    itemls.items.retain(|item| {
+        item.key.is_some() || matches!(item.term, ParseTerm::Define(_, _) | ParseTerm::ColorDef(_))
+    });
+    Ok(itemls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::source::MemSource;
+
+    fn tree(text: &str) -> Result<ParseItems, ParseError> {
+        let source = MemSource::new().add("test.pab", text);
+        let mut sources: Vec<String> = Vec::default();
+        parse_tree(&source, "test.pab", &mut sources)
+    }
+
+    fn terms(itemls: &ParseItems) -> Vec<String> {
+        itemls.items.iter().map(|nod| {
+            match &nod.key {
+                Some(key) => format!("{}={}", key, nod.term),
+                None => nod.term.to_string(),
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_indent_and_colon() {
+        let itemls = tree("mul\n  1\n  noise: grain=8, octaves=2\n").unwrap();
+        assert_eq!(terms(&itemls), vec!["mul"]);
+        let mul = &itemls.items[0];
+        assert_eq!(terms(&mul.params), vec!["1", "noise"]);
+        assert_eq!(terms(&mul.params.items[1].params), vec!["grain=8", "octaves=2"]);
+    }
+
+    #[test]
+    fn test_comments_and_header() {
+        let itemls = tree("#! title: Test\n# a comment\n\nx = 0.5\n").unwrap();
+        assert_eq!(itemls.header.len(), 1);
+        assert_eq!(itemls.header[0].0, "#! title: Test");
+        assert_eq!(terms(&itemls), vec!["x=0.5"]);
+        assert_eq!(itemls.items[0].leading, vec!["#! title: Test", "# a comment", ""]);
+    }
+
+    #[test]
+    fn test_expression() {
+        let itemls = tree("sum: 'a * 2 + 1\n").unwrap();
+        let sum = &itemls.items[0];
+        let add = &sum.params.items[0];
+        assert_eq!(add.term.to_string(), "sum");
+        assert_eq!(terms(&add.params), vec!["mul", "1"]);
+        assert_eq!(terms(&add.params.items[0].params), vec!["'a", "2"]);
+    }
+
+    #[test]
+    fn test_units_stay_words() {
+        let itemls = tree("pulser: width=10px, interval=120bpm\n").unwrap();
+        assert_eq!(terms(&itemls.items[0].params), vec!["width=10px", "interval=120bpm"]);
+        assert_eq!(split_unit("10px"), Some((10.0, "px")));
+        assert_eq!(split_unit("2.5s"), Some((2.5, "s")));
+        assert_eq!(split_unit("1e-3s"), Some((1e-3, "s")));
+        assert_eq!(split_unit("5em"), Some((5.0, "em")));
+        assert_eq!(split_unit("1e5"), None);
+        assert_eq!(split_unit("px"), None);
+    }
+
+    #[test]
+    fn test_alpha_colors() {
+        let itemls = tree("muls: $80402010, $F008, $FF8000\n").unwrap();
+        assert_eq!(terms(&itemls.items[0].params), vec!["$80402010", "$FF000088", "$FF8000"]);
+        match &itemls.items[0].params.items[1].term {
+            ParseTerm::Color(pix) => assert_eq!((pix.r, pix.a), (8.0/15.0, 8.0/15.0)),
+            _ => panic!("not a color"),
+        }
+    }
+
+    #[test]
+    fn test_errors_have_locations() {
+        let err = tree("mul\n  1\n  (2\nsum\n  4)\n").err().unwrap();
+        // Both bad lines are reported.
+        assert_eq!(err.diags.len(), 2);
+        assert_eq!(err.diags[0].message, "missing ')' in expression");
+        let loc = err.diags[0].loc.as_ref().unwrap();
+        assert_eq!((loc.file.as_ref(), loc.line, loc.column), ("test.pab", 3, 4));
+        assert_eq!(err.diags[1].loc.as_ref().unwrap().line, 5);
+    }
+
+    #[test]
+    fn test_include() {
+        let source = MemSource::new()
+            .add("dir/main.pab", "include \"lib/defs.pab\"\n'base\n")
+            .add("dir/lib/defs.pab", "base = 0.5\nmul: 1, 2\n");
+        let mut sources: Vec<String> = Vec::default();
+        let itemls = parse_tree(&source, "dir/main.pab", &mut sources).unwrap();
+        // Only the named item comes in.
+        assert_eq!(terms(&itemls), vec!["base=0.5", "'base"]);
+        assert_eq!(sources, vec!["dir/main.pab", "dir/lib/defs.pab"]);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let source = MemSource::new()
+            .add("a.pab", "include \"b.pab\"\nx = 1\n")
+            .add("b.pab", "include \"./a.pab\"\ny = 2\n");
+        let mut sources: Vec<String> = Vec::default();
+        let err = parse_tree(&source, "a.pab", &mut sources).err().unwrap();
+        assert!(err.to_string().contains("include cycle: a.pab -> b.pab -> ./a.pab"), "{}", err);
+    }
+
+    #[test]
+    fn test_include_missing() {
+        let source = MemSource::new()
+            .add("a.pab", "include \"nope.pab\"\n");
+        let mut sources: Vec<String> = Vec::default();
+        let err = parse_tree(&source, "a.pab", &mut sources).err().unwrap();
+        assert!(err.to_string().contains("nope.pab: no such script"), "{}", err);
+    }
+}
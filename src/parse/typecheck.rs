@@ -0,0 +1,168 @@
+use std::fmt;
+use std::collections::HashMap;
+
+use crate::parse::tree::{ParseTerm, ParseNode};
+use crate::parse::diag::{Diagnostic, DiagKind};
+use crate::parse::layout::{OpLayoutParam, OpLayoutType};
+use crate::parse::layout::{get_op1_layout, get_op3_layout};
+use crate::parse::match_children;
+
+// Every op yields either a scalar buffer (op1) or a color buffer (op3).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OpType {
+    Scalar,
+    Color,
+}
+
+impl fmt::Display for OpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpType::Scalar => write!(f, "scalar"),
+            OpType::Color => write!(f, "color"),
+        }
+    }
+}
+
+pub type TypeEnv = HashMap<String, OpType>;
+
+// Decide the type of a top-level item, check every op slot beneath it,
+// and insert "grey" wherever a plain scalar value fills a color slot.
+// Variable references are typed through env.
+pub fn check_item(nod: &mut ParseNode, env: &TypeEnv) -> Result<OpType, Diagnostic> {
+    // If nothing in the item settles it, prefer color.
+    let optype = synth(nod, env).unwrap_or(OpType::Color);
+    check_children(nod, optype, env)?;
+    return Ok(optype);
+}
+
+// Work out what type a node yields, if we can tell from the node alone.
+// Ops which exist in both forms ("max", "invert", "gradient"...) take
+// the type of their color-or-scalar children.
+fn synth(nod: &ParseNode, env: &TypeEnv) -> Option<OpType> {
+    match &nod.term {
+        ParseTerm::Number(_) => Some(OpType::Scalar),
+        ParseTerm::Color(_) => Some(OpType::Color),
+        ParseTerm::VarName(val) => env.get(val).copied(),
+        ParseTerm::Define(_, _) | ParseTerm::ColorDef(_) => None,
+        ParseTerm::Ident(val) => {
+            match (get_op1_layout(val), get_op3_layout(val)) {
+                (None, None) => None,
+                (Some(_), None) => Some(OpType::Scalar),
+                (None, Some(_)) => Some(OpType::Color),
+                (Some(_), Some((layout, _))) => {
+                    let pmap = match_children(nod, layout).ok()?;
+                    let mut res: Option<OpType> = None;
+                    for (slot, ix) in &pmap {
+                        match slot_type(layout, slot) {
+                            Some(OpLayoutType::Op3) | Some(OpLayoutType::Color) => {},
+                            _ => continue,
+                        }
+                        match synth(&nod.params.items[*ix], env) {
+                            Some(OpType::Color) => return Some(OpType::Color),
+                            Some(OpType::Scalar) => res = Some(OpType::Scalar),
+                            None => {},
+                        }
+                    }
+                    res
+                },
+            }
+        },
+    }
+}
+
+// Check the op slots of a node which is known to have the given type.
+fn check_children(nod: &mut ParseNode, optype: OpType, env: &TypeEnv) -> Result<(), Diagnostic> {
+    let name = match &nod.term {
+        ParseTerm::Ident(val) => val.to_string(),
+        _ => return Ok(()),
+    };
+    let layout = match optype {
+        OpType::Scalar => get_op1_layout(&name).map(|(layout, _)| layout),
+        OpType::Color => get_op3_layout(&name).map(|(layout, _)| layout),
+    };
+    // An unknown op or a bad param list will be reported when the
+    // script is built.
+    let layout = match layout {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+    let pmap = match match_children(nod, layout) {
+        Ok(val) => val,
+        Err(_) => return Ok(()),
+    };
+    let mut slots: Vec<(&String, &usize)> = pmap.iter().collect();
+    slots.sort_by_key(|(_, ix)| **ix);
+
+    for (slot, ix) in slots {
+        let want = match slot_type(layout, slot) {
+            Some(OpLayoutType::Op1) | Some(OpLayoutType::Operand) => OpType::Scalar,
+            Some(OpLayoutType::Op3) => OpType::Color,
+            _ => continue,
+        };
+        let child = &mut nod.params.items[*ix];
+        match synth(child, env) {
+            Some(got) if got != want => {
+                if want == OpType::Color && promotable(child) {
+                    promote(child);
+                    check_children(&mut child.params.items[0], OpType::Scalar, env)?;
+                }
+                else {
+                    return Err(Diagnostic::new(&child.loc, format!("`{}` yields a {} but `{}` expects a {} in {}", child.term, got, name, want, slot_desc(slot))).with_kind(DiagKind::Type));
+                }
+            },
+            Some(got) => {
+                check_children(child, got, env)?;
+            },
+            None => {
+                check_children(child, want, env)?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+// The layout entry for a slot name. Repeating slots are numbered: "_2",
+// "stop3".
+fn slot_type<'a>(layout: &'a Vec<OpLayoutParam>, slot: &str) -> Option<&'a OpLayoutType> {
+    for param in layout {
+        if param.name == slot {
+            return Some(&param.ptype);
+        }
+        if param.repeating {
+            if let Some(rest) = slot.strip_prefix(param.name.as_str()) {
+                if !rest.is_empty() && rest.chars().all(|ch| ch.is_ascii_digit()) {
+                    return Some(&param.ptype);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn slot_desc(slot: &str) -> String {
+    match slot.strip_prefix('_') {
+        Some(num) => format!("slot {}", num),
+        None => format!("`{}`", slot),
+    }
+}
+
+// A scalar may stand in for a color when it's a plain value, the same
+// everywhere on the strip. (A bare pulser in a color slot is more likely
+// a missing color than a request for grey.)
+fn promotable(nod: &ParseNode) -> bool {
+    match &nod.term {
+        ParseTerm::Number(_) => true,
+        ParseTerm::Ident(val) => val == "constant" || val == "param",
+        _ => false,
+    }
+}
+
+// Wrap a node in "grey". The wrapper takes over the slot label.
+fn promote(nod: &mut ParseNode) {
+    let mut inner = nod.clone();
+    inner.key = None;
+    let mut grey = ParseNode::new(nod.key.as_deref(), ParseTerm::Ident("grey".to_string()), nod.indent, nod.loc.clone());
+    grey.params.items.push(inner);
+    *nod = grey;
+}
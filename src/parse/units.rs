@@ -0,0 +1,78 @@
+use crate::parse::tree::{ParseItems, ParseNode, ParseTerm, split_unit};
+use crate::parse::diag::{Diagnostic, ParseError};
+
+// The physical strip, which unit suffixes are measured against.
+#[derive(Clone, Copy, Debug)]
+pub struct StripUnits {
+    pub size: usize, // pixels
+    pub ppm: f32,    // pixels per metre
+}
+
+impl StripUnits {
+    pub fn new(size: usize, ppm: f32) -> StripUnits {
+        StripUnits {
+            size: size,
+            ppm: ppm,
+        }
+    }
+}
+
+// Turn every number with a unit into a plain number. Space is measured
+// in fractions of the strip, and time in seconds:
+//    10px      (pixels)
+//    25%       (of the strip, or of whatever)
+//    0.5m      (metres)
+//    2s, 250ms (seconds, milliseconds)
+//    120bpm    (an interval: 0.5 seconds)
+// "2700K" is a color; see colors.rs.
+pub fn resolve_units(itemls: &mut ParseItems, units: &StripUnits) -> Result<(), ParseError> {
+    let mut diags: Vec<Diagnostic> = Vec::default();
+    for item in itemls.items.iter_mut() {
+        if let Err(diag) = resolve_node(item, units) {
+            diags.push(diag);
+        }
+    }
+
+    if !diags.is_empty() {
+        return Err(ParseError { diags: diags });
+    }
+    return Ok(());
+}
+
+fn resolve_node(nod: &mut ParseNode, units: &StripUnits) -> Result<(), Diagnostic> {
+    if let ParseTerm::Ident(name) = &nod.term {
+        if let Some((val, unit)) = split_unit(name) {
+            if unit != "K" {
+                if !nod.params.items.is_empty() {
+                    return Err(Diagnostic::new(&nod.loc, format!("number cannot have params: {}", name)));
+                }
+                // A tempo of zero or less has no interval.
+                if unit == "bpm" && val <= 0.0 {
+                    return Err(Diagnostic::new(&nod.loc, format!("bpm must be positive: {}", name)));
+                }
+                let num = convert(val, unit, units)
+                    .ok_or_else(|| Diagnostic::new(&nod.loc, format!("unknown unit: {}", unit)))?;
+                nod.term = ParseTerm::Number(num);
+                return Ok(());
+            }
+        }
+    }
+
+    for item in nod.params.items.iter_mut() {
+        resolve_node(item, units)?;
+    }
+    return Ok(());
+}
+
+fn convert(val: f32, unit: &str, units: &StripUnits) -> Option<f32> {
+    let size = units.size.max(1) as f32;
+    match unit {
+        "px" => Some(val / size),
+        "%" => Some(val / 100.0),
+        "m" => Some(val * units.ppm / size),
+        "s" => Some(val),
+        "ms" => Some(val / 1000.0),
+        "bpm" => Some(60.0 / val),
+        _ => None,
+    }
+}
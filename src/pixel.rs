@@ -7,21 +7,43 @@ pub struct Pix<T> {
     pub r: T,
     pub g: T,
     pub b: T,
+    pub a: T, // the color channels are premultiplied by this
 }
 
-impl<T: Copy> Pix<T> {
-    pub fn new(valr: T, valg: T, valb: T) -> Pix<T> {
-        Pix { r:valr, g:valg, b:valb }
+impl Pix<f32> {
+    pub fn new(valr: f32, valg: f32, valb: f32) -> Pix<f32> {
+        Pix { r:valr, g:valg, b:valb, a:1.0 }
     }
     
-    pub fn grey(val: T) -> Pix<T> {
-        Pix { r:val, g:val, b:val }
+    pub fn grey(val: f32) -> Pix<f32> {
+        Pix { r:val, g:val, b:val, a:1.0 }
     }
-}
 
-impl Pix<f32> {
+    // A color seen through the given opacity.
+    pub fn with_alpha(valr: f32, valg: f32, valb: f32, vala: f32) -> Pix<f32> {
+        Pix { r:valr*vala, g:valg*vala, b:valb*vala, a:vala }
+    }
+
+    // Nothing at all; anything composited over this shows through.
+    pub fn clear() -> Pix<f32> {
+        Pix { r:0.0, g:0.0, b:0.0, a:0.0 }
+    }
+
+    // Opaque colors are written "$RRGGBB", others "$RRGGBBAA".
     pub fn as_hex(&self) -> String {
-        format!("${:02X}{:02X}{:02X}", (self.r*255.0) as u8, (self.g*255.0) as u8, (self.b*255.0) as u8)
+        if self.a >= 1.0 {
+            return format!("${:02X}{:02X}{:02X}", (self.r*255.0) as u8, (self.g*255.0) as u8, (self.b*255.0) as u8);
+        }
+        // Rounded, since unpremultiplying can land just under a step.
+        let (rval, gval, bval) = self.unpremultiplied();
+        format!("${:02X}{:02X}{:02X}{:02X}", (rval*255.0).round() as u8, (gval*255.0).round() as u8, (bval*255.0).round() as u8, (self.a*255.0).round() as u8)
+    }
+
+    pub fn unpremultiplied(&self) -> (f32, f32, f32) {
+        if self.a <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        (self.r / self.a, self.g / self.a, self.b / self.a)
     }
     
     pub fn lerp(&self, other: &Pix<f32>, pos: f32) -> Pix<f32> {
@@ -29,9 +51,28 @@ impl Pix<f32> {
             r: self.r.lerp(&other.r, &pos),
             g: self.g.lerp(&other.g, &pos),
             b: self.b.lerp(&other.b, &pos),
+            a: self.a.lerp(&other.a, &pos),
         }
     }
 
+    // This color laid on top of another ("alpha-over").
+    pub fn over(&self, below: &Pix<f32>) -> Pix<f32> {
+        let rest = 1.0 - self.a.clamp(0.0, 1.0);
+        Pix {
+            r: self.r + below.r * rest,
+            g: self.g + below.g * rest,
+            b: self.b + below.b * rest,
+            a: self.a + below.a * rest,
+        }
+    }
+
+    // An opaque color, showing the background wherever this one is
+    // transparent.
+    pub fn flatten(&self, background: &Pix<f32>) -> Pix<f32> {
+        let rest = 1.0 - self.a.clamp(0.0, 1.0);
+        Pix::new(self.r + background.r * rest, self.g + background.g * rest, self.b + background.b * rest)
+    }
+
     pub fn to_hsv(&self) -> (f32, f32, f32) {
         let value = self.r.max(self.g).max(self.b);
         if value <= 0.0 {
@@ -65,7 +106,8 @@ impl Pix<f32> {
             }
         }
         else {
-            panic!("to_hsv math is wrong");
+            // Only reachable with a NaN channel; call it red.
+            hue = 0.0;
         }
         (hue/6.0, sat, value)
     }
@@ -84,11 +126,43 @@ impl Pix<f32> {
             3 => (0.0, xp, chr),
             4 => (xp, 0.0, chr),
             5 => (chr, 0.0, xp),
-            6 => (chr, xp, 0.0),
-            _ => panic!("hsv math is wrong"),
+            _ => (chr, xp, 0.0), // 6, back around to red
         };
         let m = value - chr;
         Pix::new(rval+m, gval+m, bval+m)
     }
+
+    pub fn from_hsl(hue: f32, sat: f32, light: f32) -> Pix<f32> {
+        let value = light + sat * light.min(1.0 - light);
+        if value <= 0.0 {
+            return Pix::grey(0.0);
+        }
+        Pix::from_hsv(hue, 2.0 * (1.0 - light / value), value)
+    }
+
+    // The color of a black body at the given temperature, scaled so that
+    // the brightest channel is 1. (Tanner Helland's curve fit, good from
+    // 1000K to 40000K.)
+    pub fn from_kelvin(kelvin: f32) -> Pix<f32> {
+        let temp = kelvin.clamp(1000.0, 40000.0) / 100.0;
+        let rval = if temp <= 66.0 {
+            255.0
+        } else {
+            329.69873 * (temp - 60.0).powf(-0.13320476)
+        };
+        let gval = if temp <= 66.0 {
+            99.4708 * temp.ln() - 161.11957
+        } else {
+            288.12216 * (temp - 60.0).powf(-0.075514846)
+        };
+        let bval = if temp >= 66.0 {
+            255.0
+        } else if temp <= 19.0 {
+            0.0
+        } else {
+            138.51773 * (temp - 10.0).ln() - 305.0448
+        };
+        Pix::new(rval.clamp(0.0, 255.0) / 255.0, gval.clamp(0.0, 255.0) / 255.0, bval.clamp(0.0, 255.0) / 255.0)
+    }
 }
 
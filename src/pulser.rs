@@ -1,9 +1,9 @@
-use rand::Rng;
+use std::fmt;
 
 use crate::context::scriptcontext::ScriptContext;
 use crate::runner::RunContext;
 use crate::param::Param;
-use crate::waves::WaveShape;
+use crate::waves::{WaveShape, Easing};
 
 #[derive(Clone)]
 pub struct Pulser {
@@ -12,8 +12,140 @@ pub struct Pulser {
     pub duration: Param,
     pub pos: Param,
     pub width: Param,
-    pub spaceshape: WaveShape,
-    pub timeshape: WaveShape,
+    pub spaceshape: PulseShape,
+    pub timeshape: PulseShape,
+}
+
+// The shape of a pulse, in space or in time.
+#[derive(Clone)]
+pub enum PulseShape {
+    Wave(WaveShape),   // stretched to the pulse's width or duration
+    Envelope(Envelope),
+}
+
+// An attack-decay-sustain-release envelope. The stage lengths are in
+// seconds when this is a timeshape, and fractions of the strip when it's
+// a spaceshape; either way they replace the pulser's duration or width.
+// Sustain is a level (0-1); hold is how long it's held.
+#[derive(Clone)]
+pub struct Envelope {
+    pub attack: Param,
+    pub decay: Param,
+    pub sustain: Param,
+    pub hold: Param,
+    pub release: Param,
+    pub attackease: Easing,
+    pub decayease: Easing,
+    pub releaseease: Easing,
+}
+
+impl fmt::Debug for PulseShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PulseShape::Wave(shape) => write!(f, "{:?}", shape),
+            PulseShape::Envelope(env) => write!(f, "ADSR(attack={:?}, decay={:?}, sustain={:?}, hold={:?}, release={:?}, ease={:?}/{:?}/{:?})", env.attack, env.decay, env.sustain, env.hold, env.release, env.attackease, env.decayease, env.releaseease),
+        }
+    }
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope::new()
+    }
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            attack: Param::newconst(0.1),
+            decay: Param::newconst(0.1),
+            sustain: Param::newconst(0.5),
+            hold: Param::newconst(0.5),
+            release: Param::newconst(0.3),
+            attackease: Easing::Linear,
+            decayease: Easing::Linear,
+            releaseease: Easing::Linear,
+        }
+    }
+
+    // Fix the stage params for one pulse.
+    fn resolve(&self, ctx: &ScriptContext, age: f32) -> Envelope {
+        Envelope {
+            attack: self.attack.resolve(ctx, age),
+            decay: self.decay.resolve(ctx, age),
+            sustain: self.sustain.resolve(ctx, age),
+            hold: self.hold.resolve(ctx, age),
+            release: self.release.resolve(ctx, age),
+            attackease: self.attackease,
+            decayease: self.decayease,
+            releaseease: self.releaseease,
+        }
+    }
+
+    fn release(&self, ctx: &ScriptContext) {
+        self.attack.release(ctx);
+        self.decay.release(ctx);
+        self.sustain.release(ctx);
+        self.hold.release(ctx);
+        self.release.release(ctx);
+    }
+
+    fn length(&self, ctx: &ScriptContext, age: f32) -> f32 {
+        let attack = self.attack.eval(ctx, age).max(0.0);
+        let decay = self.decay.eval(ctx, age).max(0.0);
+        let hold = self.hold.eval(ctx, age).max(0.0);
+        let release = self.release.eval(ctx, age).max(0.0);
+        attack + decay + hold + release
+    }
+
+    // The envelope level at a distance from its start.
+    fn sample(&self, ctx: &ScriptContext, age: f32, pos: f32) -> f32 {
+        if pos < 0.0 {
+            return 0.0;
+        }
+        let attack = self.attack.eval(ctx, age).max(0.0);
+        if pos < attack {
+            return self.attackease.ease(pos / attack);
+        }
+        let pos = pos - attack;
+        let sustain = self.sustain.eval(ctx, age);
+        let decay = self.decay.eval(ctx, age).max(0.0);
+        if pos < decay {
+            return 1.0 + (sustain - 1.0) * self.decayease.ease(pos / decay);
+        }
+        let pos = pos - decay;
+        let hold = self.hold.eval(ctx, age).max(0.0);
+        if pos < hold {
+            return sustain;
+        }
+        let pos = pos - hold;
+        let release = self.release.eval(ctx, age).max(0.0);
+        if pos < release {
+            return sustain * (1.0 - self.releaseease.ease(pos / release));
+        }
+        return 0.0;
+    }
+}
+
+impl PulseShape {
+    fn resolve(&self, ctx: &ScriptContext, age: f32) -> PulseShape {
+        match self {
+            PulseShape::Wave(shape) => PulseShape::Wave(*shape),
+            PulseShape::Envelope(env) => PulseShape::Envelope(env.resolve(ctx, age)),
+        }
+    }
+
+    fn release(&self, ctx: &ScriptContext) {
+        if let PulseShape::Envelope(env) = self {
+            env.release(ctx);
+        }
+    }
+}
+
+impl Default for Pulser {
+    fn default() -> Pulser {
+        Pulser::new()
+    }
 }
 
 impl Pulser {
@@ -24,9 +156,24 @@ impl Pulser {
             duration: Param::newconst(1.0),
             pos: Param::newconst(0.5),
             width: Param::newconst(0.5),
-            spaceshape: WaveShape::Triangle,
-            timeshape: WaveShape::SqrDecay,
+            spaceshape: PulseShape::Wave(WaveShape::Triangle),
+            timeshape: PulseShape::Wave(WaveShape::SqrDecay),
+        }
+    }
+
+    // Params which must be positive. Duration and width only count when
+    // a wave shape is stretched over them.
+    pub fn positive_params(&self) -> Vec<(&'static str, &Param)> {
+        let mut res: Vec<(&'static str, &Param)> = vec![("interval", &self.interval)];
+        match self.timeshape {
+            PulseShape::Wave(WaveShape::Flat) | PulseShape::Envelope(_) => {},
+            PulseShape::Wave(_) => res.push(("duration", &self.duration)),
+        }
+        match self.spaceshape {
+            PulseShape::Wave(WaveShape::Flat) | PulseShape::Envelope(_) => {},
+            PulseShape::Wave(_) => res.push(("width", &self.width)),
         }
+        res
     }
 }
 
@@ -35,8 +182,8 @@ pub struct Pulse {
     duration: Param,
     pos: Param,
     width: Param,
-    spaceshape: WaveShape,
-    timeshape: WaveShape,
+    spaceshape: PulseShape,
+    timeshape: PulseShape,
     dead: bool,
 }
 
@@ -47,6 +194,12 @@ pub struct PulserState {
     pulses: Vec<Pulse>,
 }
 
+impl Default for PulserState {
+    fn default() -> PulserState {
+        PulserState::new()
+    }
+}
+
 impl PulserState {
     pub fn new() -> PulserState {
         PulserState {
@@ -68,8 +221,8 @@ impl PulserState {
                 duration: durparam,
                 pos: posparam,
                 width: widthparam,
-                spaceshape: pulser.spaceshape,
-                timeshape: pulser.timeshape,
+                spaceshape: pulser.spaceshape.resolve(ctx, age as f32),
+                timeshape: pulser.timeshape.resolve(ctx, age as f32),
                 dead: false,
             });
 
@@ -82,6 +235,15 @@ impl PulserState {
             }
         }
 
+        for pulse in &self.pulses {
+            if pulse.dead {
+                pulse.duration.release(ctx);
+                pulse.pos.release(ctx);
+                pulse.width.release(ctx);
+                pulse.spaceshape.release(ctx);
+                pulse.timeshape.release(ctx);
+            }
+        }
         self.pulses.retain(|pulse| !pulse.dead);
     }
 
@@ -92,35 +254,45 @@ impl PulserState {
         for pulse in &mut self.pulses {
             let age = (ctx.age() - pulse.birth) as f32;
             let timeval: f32;
-            match pulse.timeshape {
-                WaveShape::Flat => {
+            match &pulse.timeshape {
+                PulseShape::Wave(WaveShape::Flat) => {
                     timeval = 1.0;
                 },
-                _ => {
+                PulseShape::Wave(shape) => {
                     let duration = pulse.duration.eval(ctx, age);
                     let time = age / duration;
                     if time > 1.0 {
                         pulse.dead = true;
                         continue;
                     }
-                    timeval = pulse.timeshape.sample(time);
+                    timeval = shape.sample(time);
+                },
+                PulseShape::Envelope(env) => {
+                    if age > env.length(ctx, age) {
+                        pulse.dead = true;
+                        continue;
+                    }
+                    timeval = env.sample(ctx, age, age);
                 }
             }
             
-            let width: f32 = pulse.width.eval(ctx, age);
+            let width: f32 = match &pulse.spaceshape {
+                PulseShape::Envelope(env) => env.length(ctx, age),
+                PulseShape::Wave(_) => pulse.width.eval(ctx, age),
+            };
             let startpos: f32;
             match pulse.spaceshape {
-                WaveShape::Flat => {
+                PulseShape::Wave(WaveShape::Flat) => {
                     startpos = 0.0;
                 },
                 _ => {
                     startpos = pulse.pos.eval(ctx, age) - width*0.5;
-                    if let Some(minpos) = pulse.pos.min(ctx, age) {
+                    if let Some(minpos) = pulse.pos.min(age) {
                         if minpos - width*0.5 > 1.0 {
                             pulse.dead = true;
                         }
                     }
-                    if let Some(maxpos) = pulse.pos.max(ctx, age) {
+                    if let Some(maxpos) = pulse.pos.max(age) {
                         if maxpos + width*0.5 < 0.0 {
                             pulse.dead = true;
                         }
@@ -133,17 +305,20 @@ impl PulserState {
             }
             
             for ix in 0..buf.len() {
-                let spaceval: f32;
-                match pulse.spaceshape {
-                    WaveShape::Flat => {
-                        spaceval = 1.0;
+                let spaceval: f32 = match &pulse.spaceshape {
+                    PulseShape::Wave(WaveShape::Flat) => {
+                        1.0
                     },
-                    _ => {
+                    PulseShape::Wave(shape) => {
                         let pos = (ix as f32) / bufrange;
                         let rpos = (pos - startpos) / width;
-                        spaceval = pulse.spaceshape.sample(rpos);
+                        shape.sample(rpos)
+                    },
+                    PulseShape::Envelope(env) => {
+                        let pos = (ix as f32) / bufrange;
+                        env.sample(ctx, age, pos - startpos)
                     }
-                }
+                };
                 let val = spaceval * timeval;
                 buf[ix] += val;
             }            
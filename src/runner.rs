@@ -1,4 +1,5 @@
 use crate::pixel::Pix;
+use crate::error::BeaconError;
 
 use crate::context::scriptcontext::{ScriptRunner, ScriptContext};
 use crate::context::limitcontext::{LimitRunner, LimitContext};
@@ -11,7 +12,11 @@ pub enum PixBuffer<'a> {
 }
 
 pub trait RunContext {
-    fn tick(&mut self) -> Result<(), String>;
+    fn tick(&mut self) -> Result<(), BeaconError>;
+
+    // Like tick, but the caller says what time it is (in seconds since
+    // the context was built).
+    fn tickat(&mut self, age: f64) -> Result<(), BeaconError>;
 
     fn age(&self) -> f64;
 
@@ -30,12 +35,14 @@ pub enum Runner {
 }
 
 impl Runner {
-    pub fn build(&self, size: usize, fixtick: Option<u32>) -> Result<RunContextWrap, String> {
+    // With a seed, the context's randomness is reproducible (given a
+    // fixed tick rate). Without one, it comes from the OS.
+    pub fn build(&self, size: usize, fixtick: Option<u32>, seed: Option<u64>) -> Result<RunContextWrap, BeaconError> {
         match self {
-            Runner::Script(run) => run.build(size, fixtick),
-            Runner::Limit(run) => run.build(size, fixtick),
-            Runner::Cycle(run) => run.build(size, fixtick),
-            Runner::WatchScript(run) => run.build(size, fixtick),
+            Runner::Script(run) => run.build(size, fixtick, seed),
+            Runner::Limit(run) => run.build(size, fixtick, seed),
+            Runner::Cycle(run) => run.build(size, fixtick, seed),
+            Runner::WatchScript(run) => run.build(size, fixtick, seed),
         }
     }
 
@@ -47,17 +54,36 @@ impl Runner {
             Runner::WatchScript(run) => run.getname(),
         }
     }
+
+    // How long this wants to run when cycling, if it has a preference.
+    pub fn getduration(&self) -> Option<f32> {
+        match self {
+            Runner::Script(run) => run.getduration(),
+            Runner::Limit(run) => run.getduration(),
+            Runner::Cycle(run) => run.getduration(),
+            Runner::WatchScript(run) => run.getduration(),
+        }
+    }
+}
+
+// A seed for a child context, mixed from the parent's seed and a count
+// (splitmix64). Children of one parent get unrelated sequences.
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    let mut val = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    val = (val ^ (val >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    val = (val ^ (val >> 27)).wrapping_mul(0x94D049BB133111EB);
+    val ^ (val >> 31)
 }
 
 pub enum RunContextWrap {
-    Script(ScriptContext),
+    Script(Box<ScriptContext>),
     Limit(LimitContext),
     Cycle(CycleContext),
     WatchScript(WatchScriptContext),
 }
 
 impl RunContext for RunContextWrap {
-    fn tick(&mut self) -> Result<(), String> {
+    fn tick(&mut self) -> Result<(), BeaconError> {
         match self {
             RunContextWrap::Script(ctx) => ctx.tick(),
             RunContextWrap::Limit(ctx) => ctx.tick(),
@@ -65,6 +91,15 @@ impl RunContext for RunContextWrap {
             RunContextWrap::WatchScript(ctx) => ctx.tick(),
         }
     }
+
+    fn tickat(&mut self, age: f64) -> Result<(), BeaconError> {
+        match self {
+            RunContextWrap::Script(ctx) => ctx.tickat(age),
+            RunContextWrap::Limit(ctx) => ctx.tickat(age),
+            RunContextWrap::Cycle(ctx) => ctx.tickat(age),
+            RunContextWrap::WatchScript(ctx) => ctx.tickat(age),
+        }
+    }
     
     fn age(&self) -> f64 {
         match self {
@@ -96,6 +131,13 @@ impl RunContext for RunContextWrap {
 }
 
 impl RunContextWrap {
+    // Copy the current frame out as colors. (A scalar script comes out
+    // grey.) The buffer must be the context's size.
+    pub fn readpixels(&self, pixels: &mut [Pix<f32>]) {
+        pixels.fill(Pix::new(0.0, 0.0, 0.0));
+        self.applybufadd(pixels, 1.0);
+    }
+
     pub fn applybufadd(&self, changebuf: &mut [Pix<f32>], scale: f32) {
         let pixsize = changebuf.len();
         
@@ -1,11 +1,10 @@
 use std::collections::HashSet;
 
-use crate::param::Param;
 use crate::op::{Op1Def, Op3Def};
 use crate::pixel::Pix;
+use crate::error::BeaconError;
+use crate::parse::diag::SourceLoc;
 
-use crate::pulser::Pulser;
-use crate::waves::WaveShape;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ScriptIndex {
@@ -17,49 +16,112 @@ pub enum ScriptIndex {
 pub struct Op1DefRef {
     pub op: Op1Def,
     pub bufs: Vec<ScriptIndex>,
+    pub parambufs: Vec<ScriptIndex>, // buffers read by the op's params
+    pub loc: Option<SourceLoc>,      // where the op was written, if anywhere
 }
 
 #[derive(Clone)]
 pub struct Op3DefRef {
     pub op: Op3Def,
     pub bufs: Vec<ScriptIndex>,
+    pub parambufs: Vec<ScriptIndex>, // buffers read by the op's params
+    pub loc: Option<SourceLoc>,      // where the op was written, if anywhere
 }
 
 impl Op1DefRef {
-    pub fn new(op: Op1Def, bufs: Vec<ScriptIndex>) -> Op1DefRef {
-        Op1DefRef { op:op, bufs:bufs }
+    pub fn new(op: Op1Def, bufs: Vec<ScriptIndex>, parambufs: Vec<ScriptIndex>, loc: Option<SourceLoc>) -> Op1DefRef {
+        Op1DefRef { op:op, bufs:bufs, parambufs:parambufs, loc:loc }
     }
     
-    pub fn get_type_ref(&self, op: u8, num: usize) -> usize {
+    pub fn get_type_ref(&self, op: u8, num: usize) -> Result<usize, BeaconError> {
         if op == 1 {
-            if let ScriptIndex::Op1(val) = self.bufs[num] {
-                return val;
+            if let Some(ScriptIndex::Op1(val)) = self.bufs.get(num) {
+                return Ok(*val);
             }
         } else if op == 3 {
-            if let ScriptIndex::Op3(val) = self.bufs[num] {
-                return val;
+            if let Some(ScriptIndex::Op3(val)) = self.bufs.get(num) {
+                return Ok(*val);
             }
         }
-        panic!("invalid typeref: type {} num {}", op, num);
+        return Err(self.err(format!("invalid typeref: type {} num {}", op, num)));
+    }
+
+    // A runtime error, at this op.
+    pub fn err(&self, message: String) -> BeaconError {
+        BeaconError::runtime(&self.loc, message)
     }
 }
 
 impl Op3DefRef {
-    pub fn new(op: Op3Def, bufs: Vec<ScriptIndex>) -> Op3DefRef {
-        Op3DefRef { op:op, bufs:bufs }
+    pub fn new(op: Op3Def, bufs: Vec<ScriptIndex>, parambufs: Vec<ScriptIndex>, loc: Option<SourceLoc>) -> Op3DefRef {
+        Op3DefRef { op:op, bufs:bufs, parambufs:parambufs, loc:loc }
     }
     
-    pub fn get_type_ref(&self, op: u8, num: usize) -> usize {
+    pub fn get_type_ref(&self, op: u8, num: usize) -> Result<usize, BeaconError> {
         if op == 1 {
-            if let ScriptIndex::Op1(val) = self.bufs[num] {
-                return val;
+            if let Some(ScriptIndex::Op1(val)) = self.bufs.get(num) {
+                return Ok(*val);
             }
         } else if op == 3 {
-            if let ScriptIndex::Op3(val) = self.bufs[num] {
-                return val;
+            if let Some(ScriptIndex::Op3(val)) = self.bufs.get(num) {
+                return Ok(*val);
             }
         }
-        panic!("invalid typeref: type {} num {}", op, num);
+        return Err(self.err(format!("invalid typeref: type {} num {}", op, num)));
+    }
+
+    // A runtime error, at this op.
+    pub fn err(&self, message: String) -> BeaconError {
+        BeaconError::runtime(&self.loc, message)
+    }
+}
+
+// Information from the "#!" header at the top of a script:
+//    #! title: Clouds
+//    #! size: 320
+//    #! tags: calm, blue
+#[derive(Clone, Default, Debug)]
+pub struct ScriptMeta {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub size: Option<usize>, // intended pixel count
+    pub fps: Option<u32>,
+    pub tags: Vec<String>,
+    pub duration: Option<f32>, // seconds, when cycling
+    pub seed: Option<u64>, // for reproducible randomness
+    pub background: Option<Pix<f32>>, // shows through transparent output
+}
+
+impl ScriptMeta {
+    pub fn dump(&self) {
+        if let Some(val) = &self.title {
+            println!("title: {}", val);
+        }
+        if let Some(val) = &self.author {
+            println!("author: {}", val);
+        }
+        if let Some(val) = &self.description {
+            println!("description: {}", val);
+        }
+        if let Some(val) = self.size {
+            println!("size: {}", val);
+        }
+        if let Some(val) = self.fps {
+            println!("fps: {}", val);
+        }
+        if !self.tags.is_empty() {
+            println!("tags: {}", self.tags.join(", "));
+        }
+        if let Some(val) = self.duration {
+            println!("duration: {}", val);
+        }
+        if let Some(val) = self.seed {
+            println!("seed: {}", val);
+        }
+        if let Some(val) = &self.background {
+            println!("background: {}", val.as_hex());
+        }
     }
 }
 
@@ -68,6 +130,8 @@ pub struct Script {
     pub order: Vec<ScriptIndex>, // 0 is root
     pub op1s: Vec<Op1DefRef>,
     pub op3s: Vec<Op3DefRef>,
+    pub sources: Vec<String>, // files read, including includes
+    pub meta: ScriptMeta,
 }
 
 struct BufTrackPair {
@@ -75,31 +139,41 @@ struct BufTrackPair {
     op3s: HashSet<usize>,
 }
 
+impl Default for Script {
+    fn default() -> Script {
+        Script::new()
+    }
+}
+
 impl Script {
     pub fn new() -> Script {
         Script {
             order: Vec::default(),
             op1s: Vec::default(),
             op3s: Vec::default(),
+            sources: Vec::default(),
+            meta: ScriptMeta::default(),
         }
     }
 
     pub fn consistency_check(&self) -> Result<(), String> {
         for ix in 0..self.order.len() {
             let scix = &self.order[ix];
-            let buflist = match scix {
+            let (buflist, parambufs) = match scix {
                 ScriptIndex::Op1(bufnum) => {
-                    &self.op1s.get(*bufnum)
-                        .ok_or_else(|| format!("SceneIndex {:?} does not exist", scix))?
-                        .bufs
+                    let opref = self.op1s.get(*bufnum)
+                        .ok_or_else(|| format!("SceneIndex {:?} does not exist", scix))?;
+                    (&opref.bufs, &opref.parambufs)
                 },
                 ScriptIndex::Op3(bufnum) => {
-                    &self.op3s.get(*bufnum)
-                        .ok_or_else(|| format!("SceneIndex {:?} does not exist", scix))?
-                        .bufs
+                    let opref = self.op3s.get(*bufnum)
+                        .ok_or_else(|| format!("SceneIndex {:?} does not exist", scix))?;
+                    (&opref.bufs, &opref.parambufs)
                 },
             };
-            for scjx in buflist {
+            // Buffers sampled by params must be computed first, just like
+            // the op's inputs.
+            for scjx in buflist.iter().chain(parambufs.iter()) {
                 if let Some(pos) = self.order.iter().position(|val| val == scjx) {
                     if pos <= ix {
                         return Err(format!("SceneIndex {:?} refers to {:?} which is earlier in the order", scix, scjx));
@@ -120,8 +194,9 @@ impl Script {
             op3s: HashSet::new(),
         };
         
+        self.meta.dump();
         println!("script has {} 1-bufs, {} 3-bufs", self.op1s.len(), self.op3s.len());
-        if self.order.len() == 0 {
+        if self.order.is_empty() {
             println!("script order is empty");
         }
         else {
@@ -151,11 +226,50 @@ impl Script {
         }
     }
 
+    // A description of the whole op tree under the root, without buffer
+    // numbers. Two scripts with the same description compute the same
+    // thing, however their ops were numbered.
+    pub fn describe_tree(&self) -> String {
+        let mut res = String::new();
+        if !self.order.is_empty() {
+            self.describeop(&mut res, self.order[0]);
+        }
+        res
+    }
+
+    fn describeop(&self, res: &mut String, scix: ScriptIndex) {
+        let (bufs, parambufs) = match scix {
+            ScriptIndex::Op1(bufnum) => {
+                res.push_str(&self.op1s[bufnum].op.describe(None));
+                (&self.op1s[bufnum].bufs, &self.op1s[bufnum].parambufs)
+            },
+            ScriptIndex::Op3(bufnum) => {
+                res.push_str(&self.op3s[bufnum].op.describe(None));
+                (&self.op3s[bufnum].bufs, &self.op3s[bufnum].parambufs)
+            },
+        };
+        res.push('[');
+        for val in bufs {
+            self.describeop(res, *val);
+            res.push(',');
+        }
+        res.push(']');
+        if !parambufs.is_empty() {
+            res.push('{');
+            for val in parambufs {
+                self.describeop(res, *val);
+                res.push(',');
+            }
+            res.push('}');
+        }
+    }
+
     fn dumpop(&self, track: &mut BufTrackPair, scix: ScriptIndex, indent: usize) {
         let indentstr: String = "  ".repeat(indent);
         let subindentstr = "\n         ".to_string() + &indentstr;
         let desc: String;
         let bufs: Option<&Vec<ScriptIndex>>;
+        let mut parambufs: Option<&Vec<ScriptIndex>> = None;
         let scstr: String;
         match scix {
             ScriptIndex::Op1(bufnum) => {
@@ -166,6 +280,7 @@ impl Script {
                 else if bufnum < self.op1s.len() {
                     track.op1s.insert(bufnum);
                     bufs = Some(&self.op1s[bufnum].bufs);
+                    parambufs = Some(&self.op1s[bufnum].parambufs);
                     desc = self.op1s[bufnum].op.describe(Some(subindentstr));
                 }
                 else {
@@ -182,6 +297,7 @@ impl Script {
                 else if bufnum < self.op3s.len() {
                     track.op3s.insert(bufnum);
                     bufs = Some(&self.op3s[bufnum].bufs);
+                    parambufs = Some(&self.op3s[bufnum].parambufs);
                     desc = self.op3s[bufnum].op.describe(Some(subindentstr));
                 }
                 else {
@@ -199,6 +315,11 @@ impl Script {
                 self.dumpop(track, *val, indent+1);
             }
         }
+        if let Some(buflist) = parambufs {
+            for val in buflist {
+                self.dumpop(track, *val, indent+1);
+            }
+        }
     }
     
 }
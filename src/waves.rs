@@ -17,7 +17,7 @@ impl WaveShape {
         match self {
             WaveShape::Flat => 1.0,
             WaveShape::Square => {
-                if pos >= 0.0 && pos < 1.0 {
+                if (0.0..1.0).contains(&pos) {
                     1.0
                 }
                 else {
@@ -25,7 +25,7 @@ impl WaveShape {
                 }
             },
             WaveShape::HalfSquare => {
-                if pos >= 0.0 && pos < 0.5 {
+                if (0.0..0.5).contains(&pos) {
                     1.0
                 }
                 else {
@@ -33,7 +33,7 @@ impl WaveShape {
                 }
             },
             WaveShape::SawTooth => {
-                if pos >= 0.0 && pos < 1.0 {
+                if (0.0..1.0).contains(&pos) {
                     pos
                 }
                 else {
@@ -41,7 +41,7 @@ impl WaveShape {
                 }
             },
             WaveShape::SqrTooth => {
-                if pos >= 0.0 && pos < 1.0 {
+                if (0.0..1.0).contains(&pos) {
                     pos*pos
                 }
                 else {
@@ -49,7 +49,7 @@ impl WaveShape {
                 }
             },
             WaveShape::SawDecay => {
-                if pos >= 0.0 && pos < 1.0 {
+                if (0.0..1.0).contains(&pos) {
                     1.0 - pos
                 }
                 else {
@@ -57,7 +57,7 @@ impl WaveShape {
                 }
             },
             WaveShape::SqrDecay => {
-                if pos >= 0.0 && pos < 1.0 {
+                if (0.0..1.0).contains(&pos) {
                     (1.0-pos)*(1.0-pos)
                 }
                 else {
@@ -65,10 +65,10 @@ impl WaveShape {
                 }
             },
             WaveShape::Triangle => {
-                if pos >= 0.0 && pos < 0.5 {
+                if (0.0..0.5).contains(&pos) {
                     pos * 2.0
                 }
-                else if pos >= 0.5 && pos < 1.0 {
+                else if (0.5..1.0).contains(&pos) {
                     (1.0 - pos) * 2.0
                 }
                 else {
@@ -76,13 +76,13 @@ impl WaveShape {
                 }
             },
             WaveShape::Trapezoid => {
-                if pos >= 0.0 && pos < 0.25 {
+                if (0.0..0.25).contains(&pos) {
                     pos * 4.0
                 }
-                else if pos >= 0.75 && pos < 1.0 {
+                else if (0.75..1.0).contains(&pos) {
                     (1.0 - pos) * 4.0
                 }
-                else if pos >= 0.25 && pos < 0.75 {
+                else if (0.25..0.75).contains(&pos) {
                     1.0
                 }
                 else {
@@ -90,7 +90,7 @@ impl WaveShape {
                 }
             },
             WaveShape::Sine => {
-                if pos >= 0.0 && pos < 1.0 {
+                if (0.0..1.0).contains(&pos) {
                     0.5 - 0.5 * (2.0*std::f32::consts::PI*pos).cos()
                 }
                 else {
@@ -100,3 +100,41 @@ impl WaveShape {
         }                
     }
 }
+
+// The curve between one keyframe and the next. A waveshape may be used
+// too; sawtooth is the same as linear.
+#[derive(Debug, Clone, Copy)]
+pub enum Easing {
+    Linear,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    Step,
+    Wave(WaveShape),
+}
+
+impl Easing {
+    // Map 0..1 (progress through the segment) to 0..1 (how far the value
+    // has moved toward the next keyframe).
+    pub fn ease(&self, pos: f32) -> f32 {
+        match self {
+            Easing::Linear => pos,
+            Easing::CubicIn => pos*pos*pos,
+            Easing::CubicOut => {
+                let inv = 1.0 - pos;
+                1.0 - inv*inv*inv
+            },
+            Easing::CubicInOut => {
+                if pos < 0.5 {
+                    4.0*pos*pos*pos
+                }
+                else {
+                    let inv = 2.0 - 2.0*pos;
+                    1.0 - 0.5*inv*inv*inv
+                }
+            },
+            Easing::Step => 0.0,
+            Easing::Wave(shape) => shape.sample(pos),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::fs;
+use std::process::Command;
+
+// Run every script in scripts/ and compare its frames against the
+// references in tests/golden. After an intentional change, re-bless with:
+//    cargo run -- --golden tests/golden --bless scripts/*.pab
+// (or run this test with BEACON_BLESS=1).
+#[test]
+fn golden_frames() {
+    let mut scripts: Vec<String> = fs::read_dir("scripts").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pab"))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_beacon"));
+    cmd.arg("--golden").arg("tests/golden");
+    if std::env::var_os("BEACON_BLESS").is_some() {
+        cmd.arg("--bless");
+    }
+    let output = cmd.args(&scripts).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "golden frames differ:\n{}", stdout);
+}